@@ -1,16 +1,754 @@
 use candid::{CandidType, Deserialize, Principal};
 use serde::Serialize;
 use std::cell::RefCell;
-use std::collections::{HashMap, BTreeMap};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::{HashMap, HashSet, BTreeMap, BTreeSet, VecDeque};
+use std::borrow::Cow;
+use std::cmp::Reverse;
 use ic_cdk::storage;
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::writer::Writer;
+use ic_stable_structures::{DefaultMemoryImpl, Memory as _, StableBTreeMap, Storable};
 
 const MAX_TITLE_LENGTH: usize = 100;
 const MAX_DESCRIPTION_LENGTH: usize = 1000;
 const PAGE_SIZE: usize = 20;
+const SECONDS_PER_DAY: u64 = 86_400;
+const MAX_NOTIFICATIONS: usize = 1000;
+const MAX_TOMBSTONES: usize = 1000;
+const MAX_CHANGELOG: usize = 5000;
+const MAX_CHANGES_PAGE: usize = 500;
+const MAX_STATUS_EVENTS: usize = 100;
+// Descriptions shorter than this are stored as-is; deflate rarely wins on them.
+const COMPRESSION_THRESHOLD: usize = 300;
+// Rough in-memory size of a Principal, used by storage_breakdown.
+const PRINCIPAL_BYTES: usize = 32;
+const MAX_ANNOUNCEMENT_LENGTH: usize = 280;
+const MAX_FLAG_NOTE_LENGTH: usize = 280;
+const SUGGESTION_THRESHOLD: u64 = 3;
+const MAX_COLLECTION_SIZE: usize = 100;
+const MAX_LATEST_RESOURCES: usize = 50;
+const MAX_RECOMMENDATIONS: usize = 20;
+const MAX_HISTOGRAM_DAYS: u64 = 366;
+const MAX_SECTION_SIZE: usize = 10;
+const MAX_FEATURED_SAMPLE: usize = 10;
+const HOMEPAGE_CACHE_SECONDS: u64 = 60;
+const PUBLISH_SWEEP_SECONDS: u64 = 60;
+const HOMEPAGE_SECTION_SIZE: usize = 3;
+const HOMEPAGE_LATEST_SIZE: usize = 10;
+const MAX_TRACKED_VIEWERS: usize = 1000;
+const EDIT_LOCK_SECONDS: u64 = 300;
+const MAX_SOURCE_URL_LENGTH: usize = 500;
+const MAX_EXTERNAL_ID_LENGTH: usize = 100;
+const MAX_SUBCATEGORY_LENGTH: usize = 50;
+const MAX_SUBCATEGORIES: usize = 20;
+const DEFAULT_RANKING_WEIGHTS: RankingWeights = RankingWeights {
+    recency: 1,
+    endorsements: 5,
+    views: 1,
+    verified_bonus: 50,
+};
+// Days after which a resource's recency points have halved.
+const RECENCY_HALF_LIFE_DAYS: u64 = 30;
+const DEFAULT_FRESHNESS_THRESHOLDS: FreshnessThresholds = FreshnessThresholds {
+    aging_after_days: 180,
+    stale_after_days: 365,
+};
+const MAX_DISCLAIMER_LENGTH: usize = 1000;
+const DEFAULT_DISCLAIMER: &str = "This content is for general information only and is not a substitute for \
+    professional medical advice. Talk to a doctor before starting, stopping or changing any treatment.";
+// Replies above 2MB fail outright, so pages stop growing well before that.
+const MAX_PAGE_BYTES: usize = 1_500_000;
+const UPGRADE_STATE_MEMORY_ID: MemoryId = MemoryId::new(0);
+const CATEGORY_INDEX_MEMORY_ID: MemoryId = MemoryId::new(1);
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
 
 #[derive(CandidType, Serialize, Deserialize, Clone)]
 pub struct EczemaResource {
+    id: u64,
+    title: String,
+    description: StoredText,
+    category: ResourceCategory,
+    created_at: u64,
+    updated_at: u64,
+    verified: bool,
+    created_by: Principal,
+    view_count: u64,
+    unique_viewers: u64,
+    publish_at: Option<u64>,
+    source_url: Option<String>,
+    reverify_required: bool,
+    requires_disclaimer: bool,
+    // Id of the resource in the external CMS it is synced from, if any.
+    external_id: Option<String>,
+    // Always set; optional only so upgrade state from before it existed still decodes.
+    content_hash: Option<u64>,
+    reading_level: Option<ReadingLevel>,
+    verified_by: Option<Principal>,
+    verified_at: Option<u64>,
+    // One of the admin-managed SUBCATEGORIES of the resource's category.
+    subcategory: Option<String>,
+}
+
+/// Read model for anonymous clients: an `EczemaResource` without the author principal.
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct PublicResource {
+    id: u64,
+    title: String,
+    description: String,
+    category: ResourceCategory,
+    created_at: u64,
+    updated_at: u64,
+    verified: bool,
+    view_count: u64,
+    unique_viewers: u64,
+    source_url: Option<String>,
+    requires_disclaimer: bool,
+    content_hash: u64,
+    reading_level: Option<ReadingLevel>,
+    subcategory: Option<String>,
+}
+
+impl From<EczemaResource> for PublicResource {
+    fn from(resource: EczemaResource) -> Self {
+        let content_hash = resource.content_hash.unwrap_or_else(|| content_hash(&resource));
+        PublicResource {
+            id: resource.id,
+            title: resource.title,
+            description: resource.description.into_string(),
+            category: resource.category,
+            created_at: resource.created_at,
+            updated_at: resource.updated_at,
+            verified: resource.verified,
+            view_count: resource.view_count,
+            unique_viewers: resource.unique_viewers,
+            source_url: resource.source_url,
+            requires_disclaimer: resource.requires_disclaimer,
+            content_hash,
+            reading_level: resource.reading_level,
+            subcategory: resource.subcategory,
+        }
+    }
+}
+
+// Text kept deflated in memory once it is long enough for that to pay off.
+// It serializes as a plain string, so the candid interface and upgrade state
+// see ordinary text either way.
+#[derive(Clone)]
+pub enum StoredText {
+    Plain(String),
+    Deflated { len: usize, bytes: Vec<u8> },
+}
+
+impl StoredText {
+    fn new(text: String) -> Self {
+        if text.len() < COMPRESSION_THRESHOLD {
+            return StoredText::Plain(text);
+        }
+        let bytes = miniz_oxide::deflate::compress_to_vec(text.as_bytes(), 6);
+        if bytes.len() >= text.len() {
+            StoredText::Plain(text)
+        } else {
+            StoredText::Deflated { len: text.len(), bytes }
+        }
+    }
+
+    fn text(&self) -> Cow<'_, str> {
+        match self {
+            StoredText::Plain(text) => Cow::Borrowed(text),
+            StoredText::Deflated { bytes, .. } => {
+                // Only ever produced from a valid string by new().
+                let raw = miniz_oxide::inflate::decompress_to_vec(bytes).expect("stored text is valid deflate");
+                Cow::Owned(String::from_utf8(raw).expect("stored text is valid UTF-8"))
+            }
+        }
+    }
+
+    fn into_string(self) -> String {
+        match self {
+            StoredText::Plain(text) => text,
+            deflated => deflated.text().into_owned(),
+        }
+    }
+
+    // Length of the original text.
+    fn len(&self) -> usize {
+        match self {
+            StoredText::Plain(text) => text.len(),
+            StoredText::Deflated { len, .. } => *len,
+        }
+    }
+
+    fn stored_len(&self) -> usize {
+        match self {
+            StoredText::Plain(text) => text.len(),
+            StoredText::Deflated { bytes, .. } => bytes.len(),
+        }
+    }
+}
+
+impl CandidType for StoredText {
+    fn _ty() -> candid::types::Type {
+        String::_ty()
+    }
+
+    fn idl_serialize<S: candid::types::Serializer>(&self, serializer: S) -> Result<(), S::Error> {
+        serializer.serialize_text(&self.text())
+    }
+}
+
+impl Serialize for StoredText {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.text())
+    }
+}
+
+impl<'de> Deserialize<'de> for StoredText {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(StoredText::new)
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum ResourceCategory {
+    Treatment,
+    Prevention,
+    Research,
+    DietAdvice,
+    Testimonial,
+    MedicalAdvice,
+}
+
+const ALL_CATEGORIES: [ResourceCategory; 6] = [
+    ResourceCategory::Treatment,
+    ResourceCategory::Prevention,
+    ResourceCategory::Research,
+    ResourceCategory::DietAdvice,
+    ResourceCategory::Testimonial,
+    ResourceCategory::MedicalAdvice,
+];
+
+// Resources in these categories are shown with the disclaimer from get_disclaimer_text.
+fn is_sensitive_category(category: ResourceCategory) -> bool {
+    matches!(category, ResourceCategory::MedicalAdvice | ResourceCategory::Treatment)
+}
+
+// FNV-1a over everything a reader sees, so it stays stable across upgrades and
+// compiler versions. View counters are left out on purpose.
+fn content_hash(resource: &EczemaResource) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut feed = |bytes: &[u8]| {
+        for &byte in bytes {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        // Separator so ("ab", "c") and ("a", "bc") differ.
+        hash ^= 0xff;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    };
+    feed(resource.title.as_bytes());
+    feed(resource.description.text().as_bytes());
+    feed(&[resource.category as u8, u8::from(resource.verified)]);
+    feed(resource.source_url.as_deref().unwrap_or_default().as_bytes());
+    feed(&resource.publish_at.unwrap_or_default().to_le_bytes());
+    feed(&[resource.reading_level.map_or(0, |level| level as u8 + 1)]);
+    // Fed only when set so hashes from before subcategories stay the same.
+    if let Some(subcategory) = &resource.subcategory {
+        feed(subcategory.as_bytes());
+    }
+    hash
+}
+
+fn refresh_content_hash(resource: &mut EczemaResource) {
+    resource.content_hash = Some(content_hash(resource));
+}
+
+// Stored as a single byte in stable memory, so variants must only ever be appended.
+impl Storable for ResourceCategory {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(vec![*self as u8])
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        match bytes[0] {
+            0 => ResourceCategory::Treatment,
+            1 => ResourceCategory::Prevention,
+            2 => ResourceCategory::Research,
+            3 => ResourceCategory::DietAdvice,
+            4 => ResourceCategory::Testimonial,
+            5 => ResourceCategory::MedicalAdvice,
+            other => ic_cdk::trap(&format!("Unknown resource category tag {other}")),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 1,
+        is_fixed_size: true,
+    };
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Contributor,
+    MedicalProfessional,
+    Moderator,
+    Admin,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct ListResponse {
+    resources: Vec<EczemaResource>,
+    // Set when the page was cut short to keep the reply under the message size limit.
+    truncated: bool,
+    // Number of matching resources across all pages.
+    total: u64,
+}
+
+// Every field is optional; only the ones set narrow the results.
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct ResourceQuery {
+    category: Option<ResourceCategory>,
+    author: Option<Principal>,
+    verified: Option<bool>,
+    text: Option<String>,
+    created_after: Option<u64>,
+    created_before: Option<u64>,
+    // Falls back to the default sort order.
+    sort: Option<SortOrder>,
+}
+
+// Fields left as None are unchanged. The double options on publish_at and
+// source_url let an admin clear them.
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct AdminResourcePatch {
+    title: Option<String>,
+    description: Option<String>,
+    category: Option<ResourceCategory>,
+    source_url: Option<Option<String>>,
+    publish_at: Option<Option<u64>>,
+    reading_level: Option<Option<ReadingLevel>>,
+    subcategory: Option<Option<String>>,
+    created_at: Option<u64>,
+    created_by: Option<Principal>,
+    verified: Option<bool>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum VerificationFilter {
+    All,
+    VerifiedOnly,
+    UnverifiedOnly,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy)]
+pub struct CategoryLimits {
+    min_description_length: u64,
+    max_description_length: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    NewestFirst,
+    OldestFirst,
+    RecentlyUpdated,
+    TitleAsc,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct CreateResourcePayload {
+    title: String,
+    description: String,
+    category: ResourceCategory,
+    publish_at: Option<u64>,
+    source_url: Option<String>,
+    reading_level: Option<ReadingLevel>,
+    subcategory: Option<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum ReadingLevel {
+    Basic,
+    Intermediate,
+    Clinical,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub enum EczemaError {
+    NotFound,
+    AlreadyExists,
+    InvalidInput(String),
+    Unauthorized,
+    InternalError,
+    ValidationErrors(Vec<String>),
+    Locked,
+    // The caller must accept_policy this version before contributing.
+    PolicyNotAccepted(u32),
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct PolicyStatus {
+    current_version: u32,
+    accepted_version: u32,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct VerificationStats {
+    total: u64,
+    verified: u64,
+    verified_percentage: f64,
+    // Mean seconds from creation to verification, over verified resources
+    // that recorded when they were verified.
+    average_seconds_to_verify: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct QualityReport {
+    id: u64,
+    title_length: u64,
+    description_length: u64,
+    verified: bool,
+    has_source: bool,
+    flag_count: u64,
+    age_days: u64,
+    score: u8,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FreshnessLevel {
+    Fresh,
+    Aging,
+    Stale,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct Freshness {
+    level: FreshnessLevel,
+    // Days since the resource was last updated.
+    age_days: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy)]
+pub struct FreshnessThresholds {
+    aging_after_days: u64,
+    stale_after_days: u64,
+}
+
+// Multipliers for list_ranked. Endorsements count followers plus collections
+// that include the resource.
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy)]
+pub struct RankingWeights {
+    recency: u64,
+    endorsements: u64,
+    views: u64,
+    verified_bonus: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationKind {
+    Updated,
+    Verified,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct Notification {
+    seq: u64,
+    resource_id: u64,
+    kind: NotificationKind,
+    timestamp: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Created,
+    Updated,
+    Recategorized,
+    Verified,
+    Deleted,
+    // An admin_edit_resource change, which may touch fields nothing else can.
+    AdminOverride,
+    // A resource became public without itself changing: its publish time
+    // passed, or its category stopped requiring verification.
+    Published,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct ChangeEvent {
+    seq: u64,
+    timestamp: u64,
+    kind: ChangeKind,
+    resource_id: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub enum StatusChange {
+    Created,
+    // Created with a future publish time.
+    Scheduled(u64),
+    Verified,
+    // An edit cleared verification (AUTO_UNVERIFY_ON_EDIT).
+    Unverified,
+    Flagged,
+    Locked,
+    Unlocked,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct StatusEvent {
+    change: StatusChange,
+    actor: Principal,
+    timestamp: u64,
+}
+
+// When a resource was first flagged and when a moderator first acted on it
+// afterwards, by verifying or locking it.
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy)]
+struct ModerationTimes {
+    first_flagged_at: u64,
+    first_moderated_at: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct SlaReport {
+    flagged: u64,
+    responded: u64,
+    average_response_seconds: Option<u64>,
+    worst_response_seconds: Option<u64>,
+    // How long the oldest flagged resource still waiting has waited.
+    oldest_pending_seconds: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct DepartureReport {
+    resources_reassigned: u64,
+    collections_reassigned: u64,
+    verifications_restamped: u64,
+    admin_removed: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct ReviewItem {
+    resource: EczemaResource,
+    priority: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct Announcement {
+    message: String,
+    created_at: u64,
+    expires_at: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct SearchHit {
+    resource: EczemaResource,
+    score: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct SearchResponse {
+    hits: Vec<SearchHit>,
+    total_matches: u64,
+    suggestion: Option<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct Collection {
+    id: u64,
+    title: String,
+    owner: Principal,
+    resource_ids: Vec<u64>,
+    created_at: u64,
+    updated_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct CollectionDetail {
+    collection: Collection,
+    resources: Vec<EczemaResource>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Default)]
+pub struct IndexHealth {
+    // Entries pointing at a missing resource or one that no longer matches the key.
+    orphaned_entries: u64,
+    // Resources that should be reachable through the index but are not.
+    missing_resources: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct IndexAudit {
+    category: IndexHealth,
+    title: IndexHealth,
+}
+
+// Approximate heap bytes per feature. Sizes are estimates from lengths and
+// counts, meant for comparing features rather than exact accounting.
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct StorageBreakdown {
+    resources: u64,
+    // Bytes kept out of `resources` by storing long descriptions deflated.
+    compression_saved: u64,
+    indexes: u64,
+    notifications: u64,
+    changelog: u64,
+    flags: u64,
+    viewers: u64,
+    collections: u64,
+    links: u64,
+    status_history: u64,
+    moderation_times: u64,
+    // Per-principal bookkeeping: first-seen times and accepted policy versions.
+    accounts: u64,
+    total: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct Template {
+    id: u64,
+    name: String,
+    title: String,
+    description: String,
+    category: ResourceCategory,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct TemplatePayload {
+    name: String,
+    title: String,
+    description: String,
+    category: ResourceCategory,
+}
+
+// Fields left as None come from the template.
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct TemplateOverrides {
+    title: Option<String>,
+    description: Option<String>,
+    category: Option<ResourceCategory>,
+    publish_at: Option<u64>,
+    source_url: Option<String>,
+    reading_level: Option<ReadingLevel>,
+    subcategory: Option<String>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct HttpRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+#[derive(CandidType, Serialize)]
+pub struct HttpResponse {
+    status_code: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FlagReason {
+    Misinformation,
+    Spam,
+    Offensive,
+    Duplicate,
+    Other(String),
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+struct Flag {
+    reporter: Principal,
+    reason: FlagReason,
+    created_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct ApiInfo {
+    version: String,
+    features: Vec<String>,
+    git_commit: Option<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct HomepageSnapshot {
+    generated_at: u64,
+    sections: Vec<(ResourceCategory, Vec<EczemaResource>)>,
+    latest: Vec<EczemaResource>,
+    featured: Vec<EczemaResource>,
+}
+
+#[derive(Clone)]
+struct EditLock {
+    holder: Principal,
+    token: u64,
+    expires_at: u64,
+}
+
+type EczemaResult<T> = Result<T, EczemaError>;
+
+// candid only encodes tuples of up to 16 values, so upgrade state is saved as
+// a single record instead, wrapped in VersionedState.
+//
+// This is the V2 shape. Optional fields can be appended to it (and to
+// EczemaResource) freely, since they decode as None from older V2 state. Any
+// other change means freezing this struct as StateV2, adding a V3 variant and
+// migrating from V2 in migrate_state.
+#[derive(CandidType, Deserialize)]
+struct StableState {
+    resources: HashMap<u64, EczemaResource>,
+    next_id: u64,
+    admins: Vec<Principal>,
+    default_sort: SortOrder,
+    subscriptions: HashMap<u64, Vec<Principal>>,
+    notifications: VecDeque<Notification>,
+    next_notification_seq: u64,
+    notification_cursors: HashMap<Principal, u64>,
+    banned_words: Vec<String>,
+    total_created: u64,
+    announcement: Option<Announcement>,
+    roles: HashMap<Principal, Role>,
+    restricted_categories: HashMap<ResourceCategory, Role>,
+    collections: HashMap<u64, Collection>,
+    next_collection_id: u64,
+    templates: HashMap<u64, Template>,
+    next_template_id: u64,
+    principal_links: HashMap<Principal, Principal>,
+    pending_links: HashMap<Principal, Principal>,
+    auto_unverify_on_edit: bool,
+    category_limits: HashMap<ResourceCategory, CategoryLimits>,
+    disclaimer_text: String,
+    links: HashMap<u64, Vec<u64>>,
+    viewers: HashMap<u64, HashSet<Principal>>,
+    spam_threshold: Option<u32>,
+    flags: Option<HashMap<u64, Vec<Flag>>>,
+    locked_resources: Option<BTreeSet<u64>>,
+    featured: Option<BTreeSet<u64>>,
+    featured_impressions: Option<HashMap<u64, u64>>,
+    tombstones: Option<VecDeque<(u64, u64)>>,
+    mask_banned: Option<bool>,
+    changelog: Option<VecDeque<ChangeEvent>>,
+    next_change_seq: Option<u64>,
+    first_seen: Option<HashMap<Principal, u64>>,
+    new_account_cooldown: Option<u64>,
+    subcategories: Option<BTreeMap<ResourceCategory, Vec<String>>>,
+    freshness_thresholds: Option<FreshnessThresholds>,
+    ranking_weights: Option<RankingWeights>,
+    notify_target: Option<Principal>,
+    policy_version: Option<u32>,
+    acked_policy: Option<HashMap<Principal, u32>>,
+    shadowbanned: Option<HashSet<Principal>>,
+    hidden_resources: Option<BTreeSet<u64>>,
+    status_history: Option<HashMap<u64, Vec<StatusEvent>>>,
+    moderation_times: Option<HashMap<u64, ModerationTimes>>,
+    requires_verification: Option<HashMap<ResourceCategory, bool>>,
+    trusted_authors: Option<Vec<Principal>>,
+    last_publish_sweep: Option<u64>,
+}
+
+// Resources as the first release stored them.
+#[derive(CandidType, Deserialize)]
+struct ResourceV1 {
     id: u64,
     title: String,
     description: String,
@@ -21,244 +759,4254 @@ pub struct EczemaResource {
     created_by: Principal,
 }
 
-#[derive(CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub enum ResourceCategory {
-    Treatment,
-    Prevention,
-    Research,
-    DietAdvice,
-    Testimonial,
-    MedicalAdvice,
+impl From<ResourceV1> for EczemaResource {
+    fn from(resource: ResourceV1) -> Self {
+        EczemaResource {
+            id: resource.id,
+            title: resource.title,
+            description: StoredText::new(resource.description),
+            category: resource.category,
+            created_at: resource.created_at,
+            updated_at: resource.updated_at,
+            verified: resource.verified,
+            created_by: resource.created_by,
+            view_count: 0,
+            unique_viewers: 0,
+            publish_at: None,
+            source_url: None,
+            reverify_required: false,
+            requires_disclaimer: is_sensitive_category(resource.category),
+            external_id: None,
+            content_hash: None,
+            reading_level: None,
+            verified_by: None,
+            verified_at: None,
+            subcategory: None,
+        }
+    }
+}
+
+// The first release's upgrade state, which it saved with storage::stable_save
+// as a (resources, category_index, next_id, admins) tuple.
+type StateV1 = (HashMap<u64, ResourceV1>, BTreeMap<ResourceCategory, Vec<u64>>, u64, Vec<Principal>);
+
+// The variant is the version tag. Only one of these exists, briefly, during an
+// upgrade, so the size difference between variants doesn't matter.
+#[derive(CandidType, Deserialize)]
+#[allow(clippy::large_enum_variant)]
+enum VersionedState {
+    V1(StateV1),
+    V2(StableState),
+}
+
+// The resource store and every index over it sit behind one RefCell, so a
+// single borrow sees them consistently and the methods below keep them in step.
+// Each mutating method also appends to CHANGELOG.
+struct State {
+    resources: HashMap<u64, EczemaResource>,
+    // Keyed by (category, id) so paging through a category is a range scan.
+    category_index: StableBTreeMap<(ResourceCategory, u64), (), Memory>,
+    // Derived from resources and rebuilt on upgrade, so neither is persisted.
+    title_index: BTreeSet<(String, u64)>,
+    external_index: HashMap<String, u64>,
+    reading_level_index: BTreeSet<(ReadingLevel, u64)>,
+    // How many resources contain each term, for recommend's IDF weights. Kept
+    // in step with every content change rather than recomputed per query.
+    document_frequency: HashMap<String, u64>,
+    next_id: u64,
+    total_created: u64,
+}
+
+impl State {
+    // The only place ids are handed out. Callers validate before inserting, so
+    // a rejected payload never consumes an id.
+    fn allocate_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    fn insert(&mut self, payload: CreateResourcePayload, caller: Principal, now: u64) -> EczemaResource {
+        let id = self.allocate_id();
+        let mut resource = EczemaResource {
+            id,
+            title: payload.title,
+            description: StoredText::new(payload.description),
+            category: payload.category,
+            created_at: now,
+            updated_at: now,
+            verified: false,
+            created_by: caller,
+            view_count: 0,
+            unique_viewers: 0,
+            publish_at: payload.publish_at,
+            source_url: payload.source_url,
+            reverify_required: false,
+            requires_disclaimer: is_sensitive_category(payload.category),
+            external_id: None,
+            content_hash: None,
+            reading_level: payload.reading_level,
+            verified_by: None,
+            verified_at: None,
+            subcategory: payload.subcategory,
+        };
+        refresh_content_hash(&mut resource);
+
+        self.category_index.insert((resource.category, id), ());
+        self.title_index.insert((normalize_title(&resource.title), id));
+        if let Some(level) = resource.reading_level {
+            self.reading_level_index.insert((level, id));
+        }
+        adjust_document_frequency(&mut self.document_frequency, &resource_terms(&resource), true);
+        self.resources.insert(id, resource.clone());
+        self.total_created += 1;
+        record_change(ChangeKind::Created, id, now);
+        resource
+    }
+
+    // Replaces the editable fields and re-keys the title and category indexes.
+    // Verified resources are flagged for re-verification, and unverified
+    // outright when AUTO_UNVERIFY_ON_EDIT is set.
+    fn replace_content(&mut self, id: u64, payload: CreateResourcePayload, now: u64) -> Option<&mut EczemaResource> {
+        let resource = self.resources.get_mut(&id)?;
+        self.title_index.remove(&(normalize_title(&resource.title), id));
+        self.title_index.insert((normalize_title(&payload.title), id));
+        if resource.category != payload.category {
+            self.category_index.remove(&(resource.category, id));
+            self.category_index.insert((payload.category, id), ());
+        }
+        if let Some(level) = resource.reading_level {
+            self.reading_level_index.remove(&(level, id));
+        }
+        if let Some(level) = payload.reading_level {
+            self.reading_level_index.insert((level, id));
+        }
+        adjust_document_frequency(&mut self.document_frequency, &resource_terms(resource), false);
+        resource.title = payload.title;
+        resource.description = StoredText::new(payload.description);
+        resource.category = payload.category;
+        resource.requires_disclaimer = is_sensitive_category(payload.category);
+        resource.publish_at = payload.publish_at;
+        resource.source_url = payload.source_url;
+        resource.reading_level = payload.reading_level;
+        resource.subcategory = payload.subcategory;
+        resource.updated_at = now;
+        if resource.verified {
+            resource.reverify_required = true;
+            if AUTO_UNVERIFY_ON_EDIT.with(|flag| *flag.borrow()) {
+                resource.verified = false;
+                resource.verified_by = None;
+                resource.verified_at = None;
+            }
+        }
+        refresh_content_hash(resource);
+        adjust_document_frequency(&mut self.document_frequency, &resource_terms(resource), true);
+        record_change(ChangeKind::Updated, id, now);
+        Some(resource)
+    }
+
+    fn move_to_category(&mut self, id: u64, category: ResourceCategory, now: u64) {
+        if let Some(resource) = self.resources.get_mut(&id) {
+            self.category_index.remove(&(resource.category, id));
+            self.category_index.insert((category, id), ());
+            resource.category = category;
+            resource.requires_disclaimer = is_sensitive_category(category);
+            // Subcategories belong to one parent, so they don't survive the move.
+            resource.subcategory = None;
+            resource.updated_at = now;
+            refresh_content_hash(resource);
+            record_change(ChangeKind::Recategorized, id, now);
+        }
+    }
+
+    fn set_external_id(&mut self, id: u64, external_id: String) -> Option<&mut EczemaResource> {
+        let resource = self.resources.get_mut(&id)?;
+        if let Some(previous) = resource.external_id.replace(external_id.clone()) {
+            self.external_index.remove(&previous);
+        }
+        self.external_index.insert(external_id, id);
+        Some(resource)
+    }
+
+    fn remove(&mut self, id: u64, now: u64) -> Option<EczemaResource> {
+        let resource = self.resources.remove(&id)?;
+        self.category_index.remove(&(resource.category, id));
+        self.title_index.remove(&(normalize_title(&resource.title), id));
+        if let Some(level) = resource.reading_level {
+            self.reading_level_index.remove(&(level, id));
+        }
+        if let Some(external_id) = &resource.external_id {
+            self.external_index.remove(external_id);
+        }
+        adjust_document_frequency(&mut self.document_frequency, &resource_terms(&resource), false);
+        record_change(ChangeKind::Deleted, id, now);
+        Some(resource)
+    }
+
+    // Content hashes are recomputed too, which fills them in for resources
+    // saved before they existed.
+    fn rebuild_derived_indexes(&mut self) {
+        for resource in self.resources.values_mut() {
+            refresh_content_hash(resource);
+        }
+        self.title_index = self.resources.values().map(|r| (normalize_title(&r.title), r.id)).collect();
+        self.external_index = self
+            .resources
+            .values()
+            .filter_map(|r| r.external_id.clone().map(|external_id| (external_id, r.id)))
+            .collect();
+        self.reading_level_index = self
+            .resources
+            .values()
+            .filter_map(|r| r.reading_level.map(|level| (level, r.id)))
+            .collect();
+        self.document_frequency = HashMap::new();
+        for resource in self.resources.values() {
+            adjust_document_frequency(&mut self.document_frequency, &resource_terms(resource), true);
+        }
+    }
+}
+
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| w.len() >= 3)
+        .map(str::to_lowercase)
+}
+
+fn resource_terms(resource: &EczemaResource) -> HashSet<String> {
+    tokenize(&resource.title).chain(tokenize(&resource.description.text())).collect()
+}
+
+fn adjust_document_frequency(frequency: &mut HashMap<String, u64>, terms: &HashSet<String>, add: bool) {
+    for term in terms {
+        if add {
+            *frequency.entry(term.clone()).or_default() += 1;
+        } else if let Some(count) = frequency.get_mut(term) {
+            *count -= 1;
+            if *count == 0 {
+                frequency.remove(term);
+            }
+        }
+    }
+}
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+    static STATE: RefCell<State> = RefCell::new(State {
+        resources: HashMap::new(),
+        category_index: StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(CATEGORY_INDEX_MEMORY_ID))),
+        title_index: BTreeSet::new(),
+        external_index: HashMap::new(),
+        reading_level_index: BTreeSet::new(),
+        document_frequency: HashMap::new(),
+        next_id: 1,
+        total_created: 0,
+    });
+    static ADMINS: RefCell<Vec<Principal>> = const { RefCell::new(Vec::new()) };
+    static DEFAULT_SORT: RefCell<SortOrder> = const { RefCell::new(SortOrder::NewestFirst) };
+    static SUBSCRIPTIONS: RefCell<HashMap<u64, Vec<Principal>>> = RefCell::new(HashMap::new());
+    static NOTIFICATIONS: RefCell<VecDeque<Notification>> = const { RefCell::new(VecDeque::new()) };
+    static NEXT_NOTIFICATION_SEQ: RefCell<u64> = const { RefCell::new(1) };
+    static NOTIFICATION_CURSORS: RefCell<HashMap<Principal, u64>> = RefCell::new(HashMap::new());
+    static BANNED_WORDS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    static ANNOUNCEMENT: RefCell<Option<Announcement>> = const { RefCell::new(None) };
+    static ROLES: RefCell<HashMap<Principal, Role>> = RefCell::new(HashMap::new());
+    static RESTRICTED_CATEGORIES: RefCell<HashMap<ResourceCategory, Role>> = RefCell::new(HashMap::new());
+    static COLLECTIONS: RefCell<HashMap<u64, Collection>> = RefCell::new(HashMap::new());
+    static NEXT_COLLECTION_ID: RefCell<u64> = const { RefCell::new(1) };
+    static TEMPLATES: RefCell<HashMap<u64, Template>> = RefCell::new(HashMap::new());
+    static NEXT_TEMPLATE_ID: RefCell<u64> = const { RefCell::new(1) };
+    // Linked principal -> primary principal, and pending requests keyed the same way.
+    static PRINCIPAL_LINKS: RefCell<HashMap<Principal, Principal>> = RefCell::new(HashMap::new());
+    static PENDING_LINKS: RefCell<HashMap<Principal, Principal>> = RefCell::new(HashMap::new());
+    static AUTO_UNVERIFY_ON_EDIT: RefCell<bool> = const { RefCell::new(false) };
+    static CATEGORY_LIMITS: RefCell<HashMap<ResourceCategory, CategoryLimits>> = RefCell::new(HashMap::new());
+    static DISCLAIMER_TEXT: RefCell<String> = RefCell::new(DEFAULT_DISCLAIMER.to_string());
+    // Manual "see also" links, stored in both directions.
+    static LINKS: RefCell<HashMap<u64, Vec<u64>>> = RefCell::new(HashMap::new());
+    // Capped at MAX_TRACKED_VIEWERS per resource; see record_view.
+    static VIEWERS: RefCell<HashMap<u64, HashSet<Principal>>> = RefCell::new(HashMap::new());
+    // Creations scoring above this are rejected; None disables the check.
+    static SPAM_THRESHOLD: RefCell<Option<u32>> = const { RefCell::new(None) };
+    static FLAGS: RefCell<HashMap<u64, Vec<Flag>>> = RefCell::new(HashMap::new());
+    // Finalized resources only admins may edit. Unlike EDIT_LOCKS this is persisted.
+    static LOCKED_RESOURCES: RefCell<BTreeSet<u64>> = const { RefCell::new(BTreeSet::new()) };
+    static FEATURED: RefCell<BTreeSet<u64>> = const { RefCell::new(BTreeSet::new()) };
+    // How often each featured resource was served by sample_featured; compare
+    // against view_count for click-through.
+    static FEATURED_IMPRESSIONS: RefCell<HashMap<u64, u64>> = RefCell::new(HashMap::new());
+    // (computed_at, snapshot); rebuilt on a timer and never persisted.
+    static HOMEPAGE_CACHE: RefCell<Option<(u64, HomepageSnapshot)>> = const { RefCell::new(None) };
+    // (deleted id, deleted at) for the last MAX_TOMBSTONES deletions, oldest first.
+    static TOMBSTONES: RefCell<VecDeque<(u64, u64)>> = const { RefCell::new(VecDeque::new()) };
+    // The last MAX_CHANGELOG mutations, oldest first, numbered from NEXT_CHANGE_SEQ.
+    static CHANGELOG: RefCell<VecDeque<ChangeEvent>> = const { RefCell::new(VecDeque::new()) };
+    static NEXT_CHANGE_SEQ: RefCell<u64> = const { RefCell::new(1) };
+    // Publish times up to this one have already been announced in the changelog.
+    static LAST_PUBLISH_SWEEP: RefCell<u64> = const { RefCell::new(0) };
+    // When each principal first made an update call, and how many seconds must
+    // pass after that before it may create resources (0 disables the check).
+    static FIRST_SEEN: RefCell<HashMap<Principal, u64>> = RefCell::new(HashMap::new());
+    static NEW_ACCOUNT_COOLDOWN: RefCell<u64> = const { RefCell::new(0) };
+    static SUBCATEGORIES: RefCell<BTreeMap<ResourceCategory, Vec<String>>> = const { RefCell::new(BTreeMap::new()) };
+    static FRESHNESS_THRESHOLDS: RefCell<FreshnessThresholds> = const { RefCell::new(DEFAULT_FRESHNESS_THRESHOLDS) };
+    static RANKING_WEIGHTS: RefCell<RankingWeights> = const { RefCell::new(DEFAULT_RANKING_WEIGHTS) };
+    // Canister told about each verification through its on_resource_verified method.
+    static NOTIFY_TARGET: RefCell<Option<Principal>> = const { RefCell::new(None) };
+    // Version 0 means no policy has been published, so nobody needs to accept one.
+    static POLICY_VERSION: RefCell<u32> = const { RefCell::new(0) };
+    static ACKED_POLICY: RefCell<HashMap<Principal, u32>> = RefCell::new(HashMap::new());
+    // Resources created while their author was shadowbanned. Only the author
+    // and admins see them; nothing in the resource itself says so.
+    static SHADOWBANNED: RefCell<HashSet<Principal>> = RefCell::new(HashSet::new());
+    static HIDDEN_RESOURCES: RefCell<BTreeSet<u64>> = const { RefCell::new(BTreeSet::new()) };
+    // Lifecycle events per resource, oldest first, up to MAX_STATUS_EVENTS each.
+    static STATUS_HISTORY: RefCell<HashMap<u64, Vec<StatusEvent>>> = RefCell::new(HashMap::new());
+    static MODERATION_TIMES: RefCell<HashMap<u64, ModerationTimes>> = RefCell::new(HashMap::new());
+    // Categories whose resources stay out of public reads until verified.
+    static REQUIRES_VERIFICATION: RefCell<HashMap<ResourceCategory, bool>> = RefCell::new(HashMap::new());
+    // Resources these principals create are verified on creation.
+    static TRUSTED_AUTHORS: RefCell<Vec<Principal>> = const { RefCell::new(Vec::new()) };
+    static MASK_BANNED: RefCell<bool> = const { RefCell::new(false) };
+    // Advisory locks are short-lived, so they are deliberately not persisted across upgrades.
+    static EDIT_LOCKS: RefCell<HashMap<u64, EditLock>> = RefCell::new(HashMap::new());
+    static NEXT_LOCK_TOKEN: RefCell<u64> = const { RefCell::new(1) };
+}
+
+#[cfg(feature = "testing")]
+thread_local! {
+    static MOCK_TIME: RefCell<Option<u64>> = const { RefCell::new(None) };
+}
+
+// Fixes the clock for time-dependent tests; 0 goes back to real time.
+#[cfg(feature = "testing")]
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn set_mock_time(secs: u64) -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    MOCK_TIME.with(|t| *t.borrow_mut() = (secs > 0).then_some(secs));
+    Ok(())
+}
+
+fn get_timestamp() -> u64 {
+    #[cfg(feature = "testing")]
+    if let Some(secs) = MOCK_TIME.with(|t| *t.borrow()) {
+        return secs;
+    }
+    // SystemTime traps on wasm32; the replica's clock is nanoseconds since the epoch.
+    ic_cdk::api::time() / 1_000_000_000
+}
+
+fn validate_payload(payload: &CreateResourcePayload) -> EczemaResult<()> {
+    let mut errors = Vec::new();
+    // Deployments that mask banned words accept them and hide them on read instead.
+    let masking = MASK_BANNED.with(|m| *m.borrow());
+    if payload.title.is_empty() || payload.title.len() > MAX_TITLE_LENGTH {
+        errors.push("Invalid title length".to_string());
+    } else if !masking && contains_banned_word(&payload.title) {
+        errors.push("Title contains a banned term".to_string());
+    }
+    let limits = category_limits(payload.category);
+    let description_length = payload.description.len() as u64;
+    if description_length < limits.min_description_length || description_length > limits.max_description_length {
+        errors.push(format!(
+            "{:?} descriptions must be between {} and {} characters",
+            payload.category, limits.min_description_length, limits.max_description_length
+        ));
+    } else if !masking && contains_banned_word(&payload.description) {
+        errors.push("Description contains a banned term".to_string());
+    }
+    if payload.publish_at.is_some_and(|t| t < get_timestamp()) {
+        errors.push("Publish time is in the past".to_string());
+    }
+    if let Some(url) = &payload.source_url {
+        if !url.starts_with("https://") || url.len() > MAX_SOURCE_URL_LENGTH {
+            errors.push("Source URL must be an https:// link of at most 500 characters".to_string());
+        }
+    }
+    if let Some(subcategory) = &payload.subcategory {
+        let allowed = SUBCATEGORIES
+            .with(|s| s.borrow().get(&payload.category).is_some_and(|subs| subs.contains(subcategory)));
+        if !allowed {
+            errors.push(format!("{:?} has no subcategory named {}", payload.category, subcategory));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(EczemaError::ValidationErrors(errors))
+    }
+}
+
+// Research and medical guidance need room for context; testimonials can be brief.
+fn default_category_limits(category: ResourceCategory) -> CategoryLimits {
+    let min_description_length = match category {
+        ResourceCategory::Research => 200,
+        ResourceCategory::MedicalAdvice => 100,
+        ResourceCategory::Treatment => 50,
+        ResourceCategory::Prevention | ResourceCategory::DietAdvice => 30,
+        ResourceCategory::Testimonial => 1,
+    };
+    CategoryLimits {
+        min_description_length,
+        max_description_length: MAX_DESCRIPTION_LENGTH as u64,
+    }
+}
+
+fn category_limits(category: ResourceCategory) -> CategoryLimits {
+    CATEGORY_LIMITS
+        .with(|limits| limits.borrow().get(&category).copied())
+        .unwrap_or_else(|| default_category_limits(category))
+}
+
+fn contains_banned_word(text: &str) -> bool {
+    BANNED_WORDS.with(|banned| {
+        let banned = banned.borrow();
+        if banned.is_empty() {
+            return false;
+        }
+        text.to_lowercase()
+            .split(|c: char| !c.is_alphanumeric())
+            .any(|word| banned.iter().any(|b| b == word))
+    })
+}
+
+fn mask_banned_words(text: &str) -> String {
+    BANNED_WORDS.with(|banned| {
+        let banned = banned.borrow();
+        let mut masked = String::with_capacity(text.len());
+        let mut word = String::new();
+        let flush = |word: &mut String, masked: &mut String| {
+            if banned.contains(&word.to_lowercase()) {
+                masked.extend(std::iter::repeat_n('*', word.chars().count()));
+            } else {
+                masked.push_str(word);
+            }
+            word.clear();
+        };
+        for c in text.chars() {
+            if c.is_alphanumeric() {
+                word.push(c);
+            } else {
+                flush(&mut word, &mut masked);
+                masked.push(c);
+            }
+        }
+        flush(&mut word, &mut masked);
+        masked
+    })
+}
+
+fn display_text(text: &str) -> String {
+    if MASK_BANNED.with(|m| *m.borrow()) {
+        mask_banned_words(text)
+    } else {
+        text.to_string()
+    }
+}
+
+// Applied to every resource on its way out of a read endpoint. Stored text is
+// never masked, so turning MASK_BANNED off restores the original wording.
+// The hash is recomputed over the masked text, so a change to the banned list
+// also invalidates copies clients cached through get_resource_if_changed.
+fn masked(mut resource: EczemaResource) -> EczemaResource {
+    if MASK_BANNED.with(|m| *m.borrow()) {
+        resource.title = mask_banned_words(&resource.title);
+        resource.description = StoredText::new(mask_banned_words(&resource.description.text()));
+        refresh_content_hash(&mut resource);
+    }
+    resource
+}
+
+fn payload_from_resource(resource: &EczemaResource) -> CreateResourcePayload {
+    CreateResourcePayload {
+        title: resource.title.clone(),
+        description: resource.description.text().into_owned(),
+        category: resource.category,
+        // The schedule has already been accepted, so it is not revalidated.
+        publish_at: None,
+        source_url: resource.source_url.clone(),
+        reading_level: resource.reading_level,
+        subcategory: resource.subcategory.clone(),
+    }
+}
+
+fn is_admin(caller: Principal) -> bool {
+    ADMINS.with(|admins| admins.borrow().contains(&caller))
+}
+
+fn notify_followers(resource_id: u64, kind: NotificationKind) {
+    let has_followers = SUBSCRIPTIONS.with(|s| s.borrow().get(&resource_id).is_some_and(|f| !f.is_empty()));
+    if !has_followers {
+        return;
+    }
+
+    NEXT_NOTIFICATION_SEQ.with(|next_seq| {
+        NOTIFICATIONS.with(|notifications| {
+            let mut notifications = notifications.borrow_mut();
+            let seq = *next_seq.borrow();
+            notifications.push_back(Notification {
+                seq,
+                resource_id,
+                kind,
+                timestamp: get_timestamp(),
+            });
+            if notifications.len() > MAX_NOTIFICATIONS {
+                notifications.pop_front();
+            }
+            *next_seq.borrow_mut() += 1;
+        })
+    })
+}
+
+fn record_status(resource_id: u64, change: StatusChange, actor: Principal) {
+    STATUS_HISTORY.with(|history| {
+        let mut history = history.borrow_mut();
+        let events = history.entry(resource_id).or_default();
+        events.push(StatusEvent {
+            change,
+            actor,
+            timestamp: get_timestamp(),
+        });
+        if events.len() > MAX_STATUS_EVENTS {
+            events.remove(0);
+        }
+    });
+}
+
+fn note_moderated(resource_id: u64, now: u64) {
+    MODERATION_TIMES.with(|times| {
+        if let Some(entry) = times.borrow_mut().get_mut(&resource_id) {
+            entry.first_moderated_at.get_or_insert(now);
+        }
+    });
+}
+
+fn record_change(kind: ChangeKind, resource_id: u64, now: u64) {
+    let seq = NEXT_CHANGE_SEQ.with(|next_seq| {
+        let mut next_seq = next_seq.borrow_mut();
+        *next_seq += 1;
+        *next_seq - 1
+    });
+    CHANGELOG.with(|changelog| {
+        let mut changelog = changelog.borrow_mut();
+        changelog.push_back(ChangeEvent {
+            seq,
+            timestamp: now,
+            kind,
+            resource_id,
+        });
+        if changelog.len() > MAX_CHANGELOG {
+            changelog.pop_front();
+        }
+    });
+}
+
+fn note_first_seen(caller: Principal, now: u64) -> u64 {
+    if caller == Principal::anonymous() {
+        return now;
+    }
+    FIRST_SEEN.with(|seen| *seen.borrow_mut().entry(caller).or_insert(now))
+}
+
+// Guard on every update endpoint, so an account's age counts from its first
+// call of any kind, not just its first create.
+fn note_caller() -> Result<(), String> {
+    note_first_seen(ic_cdk::caller(), get_timestamp());
+    Ok(())
+}
+
+fn accepted_policy_version(principal: Principal) -> u32 {
+    ACKED_POLICY.with(|acked| acked.borrow().get(&principal).copied().unwrap_or_default())
+}
+
+fn check_policy_accepted(caller: Principal) -> EczemaResult<()> {
+    let current = POLICY_VERSION.with(|v| *v.borrow());
+    if accepted_policy_version(caller) < current {
+        return Err(EczemaError::PolicyNotAccepted(current));
+    }
+    Ok(())
+}
+
+// The rejected call still records first-seen, so the wait starts from a
+// newcomer's first attempt at the latest.
+fn check_new_account(caller: Principal) -> EczemaResult<()> {
+    let now = get_timestamp();
+    let first_seen = note_first_seen(caller, now);
+    let cooldown = NEW_ACCOUNT_COOLDOWN.with(|c| *c.borrow());
+    if cooldown == 0 || role_of(caller) >= Role::Moderator {
+        return Ok(());
+    }
+    let allowed_at = first_seen.saturating_add(cooldown);
+    if now < allowed_at {
+        return Err(EczemaError::InvalidInput(format!(
+            "New accounts can create resources {} seconds after first use; try again in {} seconds",
+            cooldown,
+            allowed_at - now
+        )));
+    }
+    Ok(())
+}
+
+// Linked principals (the same person on another device) act as their primary.
+fn resolve_principal(principal: Principal) -> Principal {
+    PRINCIPAL_LINKS.with(|links| links.borrow().get(&principal).copied().unwrap_or(principal))
+}
+
+fn same_owner(a: Principal, b: Principal) -> bool {
+    resolve_principal(a) == resolve_principal(b)
+}
+
+// Hidden resources never go live, and neither do unverified ones in a
+// category that requires verification, so every public read path leaves
+// them out.
+fn is_published(resource: &EczemaResource, now: u64) -> bool {
+    is_due(resource, now)
+        && !is_hidden(resource.id)
+        && (resource.verified || !requires_verification(resource.category))
+}
+
+// Past its publish time, whether or not anything else keeps it from going live.
+fn is_due(resource: &EczemaResource, now: u64) -> bool {
+    resource.publish_at.is_none_or(|t| t <= now)
+}
+
+fn requires_verification(category: ResourceCategory) -> bool {
+    REQUIRES_VERIFICATION.with(|r| r.borrow().get(&category).copied().unwrap_or_default())
+}
+
+fn is_hidden(id: u64) -> bool {
+    HIDDEN_RESOURCES.with(|h| h.borrow().contains(&id))
+}
+
+fn is_shadowbanned(principal: Principal) -> bool {
+    SHADOWBANNED.with(|s| s.borrow().contains(&resolve_principal(principal)))
+}
+
+// Scheduled resources stay hidden from everyone except their author and admins
+// until publish_at passes; visibility is computed at query time.
+fn is_visible_to(resource: &EczemaResource, caller: Principal, now: u64) -> bool {
+    is_published(resource, now) || same_owner(resource.created_by, caller) || is_admin(caller)
+}
+
+fn normalize_title(title: &str) -> String {
+    title.trim().to_lowercase()
+}
+
+// Members of ADMINS always rank as Admin; everyone else defaults to Contributor.
+fn role_of(principal: Principal) -> Role {
+    if is_admin(principal) {
+        return Role::Admin;
+    }
+    ROLES.with(|roles| roles.borrow().get(&principal).copied().unwrap_or(Role::Contributor))
+}
+
+// Locked resources are admin-only regardless of authorship or role.
+fn may_edit(resource: &EczemaResource, caller: Principal) -> bool {
+    if is_locked(resource.id) {
+        return is_admin(caller);
+    }
+    same_owner(resource.created_by, caller) || role_of(caller) >= Role::Moderator
+}
+
+fn locked_by_other(id: u64, caller: Principal, now: u64) -> bool {
+    EDIT_LOCKS.with(|locks| {
+        locks
+            .borrow()
+            .get(&id)
+            .is_some_and(|lock| !same_owner(lock.holder, caller) && lock.expires_at > now)
+    })
+}
+
+fn is_locked(id: u64) -> bool {
+    LOCKED_RESOURCES.with(|locked| locked.borrow().contains(&id))
+}
+
+fn check_category_access(category: ResourceCategory, caller: Principal) -> EczemaResult<()> {
+    let required = RESTRICTED_CATEGORIES.with(|r| r.borrow().get(&category).copied());
+    match required {
+        Some(role) if role_of(caller) < role => Err(EczemaError::Unauthorized),
+        _ => Ok(()),
+    }
+}
+
+// Rough candid size: the variable-length text plus a fixed allowance for ids,
+// timestamps, the principal and record framing.
+fn approximate_size(resource: &EczemaResource) -> usize {
+    resource.title.len()
+        + resource.description.len()
+        + resource.source_url.as_ref().map_or(0, String::len)
+        + 128
+}
+
+// Walks every item to count the total, but only clones the requested page.
+fn build_page<'a>(items: impl Iterator<Item = &'a EczemaResource>, page: usize) -> ListResponse {
+    let mut resources = Vec::new();
+    let mut bytes = 0;
+    let mut truncated = false;
+    let mut total = 0;
+    for (index, resource) in items.enumerate() {
+        total += 1;
+        if truncated || index < page * PAGE_SIZE || resources.len() >= PAGE_SIZE {
+            continue;
+        }
+        bytes += approximate_size(resource);
+        if bytes > MAX_PAGE_BYTES && !resources.is_empty() {
+            truncated = true;
+            continue;
+        }
+        resources.push(masked(resource.clone()));
+    }
+    ListResponse { resources, truncated, total }
+}
+
+fn sort_resources(resources: &mut [EczemaResource], order: SortOrder) {
+    resources.sort_by(|a, b| compare_resources(a, b, order));
+}
+
+fn compare_resources(a: &EczemaResource, b: &EczemaResource, order: SortOrder) -> std::cmp::Ordering {
+    match order {
+        SortOrder::NewestFirst => b.created_at.cmp(&a.created_at).then(b.id.cmp(&a.id)),
+        SortOrder::OldestFirst => a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)),
+        SortOrder::RecentlyUpdated => b.updated_at.cmp(&a.updated_at).then(b.id.cmp(&a.id)),
+        SortOrder::TitleAsc => a.title.to_lowercase().cmp(&b.title.to_lowercase()).then(a.id.cmp(&b.id)),
+    }
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn create_resource(payload: CreateResourcePayload) -> EczemaResult<EczemaResource> {
+    create_as(payload, ic_cdk::caller())
+}
+
+// Every create-time policy lives here, so all the ways of making a new
+// resource apply the same checks and auto-verification.
+fn create_as(payload: CreateResourcePayload, caller: Principal) -> EczemaResult<EczemaResource> {
+    validate_payload(&payload)?;
+    if SPAM_THRESHOLD.with(|t| *t.borrow()).is_some_and(|threshold| spam_score(payload.clone()) > threshold) {
+        return Err(EczemaError::InvalidInput("Resource looks like spam".to_string()));
+    }
+    check_policy_accepted(caller)?;
+    check_new_account(caller)?;
+    check_category_access(payload.category, caller)?;
+    let resource = insert_resource(payload, caller);
+    if !is_trusted_author(caller) {
+        return Ok(resource);
+    }
+    // The canister's own id as verifier marks the verification as automatic.
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let resource = state.resources.get_mut(&resource.id).ok_or(EczemaError::InternalError)?;
+        mark_verified(resource, ic_cdk::id(), get_timestamp());
+        Ok(resource.clone())
+    })
+}
+
+fn is_trusted_author(principal: Principal) -> bool {
+    let principal = resolve_principal(principal);
+    TRUSTED_AUTHORS.with(|t| t.borrow().contains(&principal))
+}
+
+fn insert_resource(payload: CreateResourcePayload, caller: Principal) -> EczemaResource {
+    let resource = STATE.with(|state| state.borrow_mut().insert(payload, caller, get_timestamp()));
+    record_status(resource.id, StatusChange::Created, caller);
+    if let Some(at) = resource.publish_at {
+        record_status(resource.id, StatusChange::Scheduled(at), caller);
+    }
+    if is_shadowbanned(caller) {
+        HIDDEN_RESOURCES.with(|h| h.borrow_mut().insert(resource.id));
+    }
+    resource
+}
+
+const DEMO_RESOURCES: [(&str, ResourceCategory, &str); 6] = [
+    (
+        "Soak and smear for severe flares",
+        ResourceCategory::Treatment,
+        "Soak in a lukewarm bath for ten minutes, pat the skin almost dry, then apply a thick layer of \
+         prescribed ointment followed by plain emollient to lock the moisture in overnight.",
+    ),
+    (
+        "Choosing a gentle laundry routine",
+        ResourceCategory::Prevention,
+        "Fragrance-free detergents, an extra rinse cycle and skipping fabric softener reduce the residue \
+         left on clothes and bedding that can irritate sensitive skin.",
+    ),
+    (
+        "Summary: barrier repair and emollient therapy",
+        ResourceCategory::Research,
+        "Several randomised trials have compared regular emollient use with usual care in people with \
+         atopic eczema. Participants who moisturised at least twice daily reported fewer flares and used \
+         less topical steroid over the study period, although results varied with the product used and \
+         how consistently it was applied.",
+    ),
+    (
+        "Keeping a food and symptom diary",
+        ResourceCategory::DietAdvice,
+        "Note meals, snacks and skin changes for a few weeks before cutting anything out, and bring the \
+         diary to your doctor so suspected triggers can be tested properly.",
+    ),
+    (
+        "What finally helped my daughter sleep",
+        ResourceCategory::Testimonial,
+        "Cotton sleepsuits with fold-over mitts and a cooler bedroom stopped most of the night-time \
+         scratching within a couple of weeks.",
+    ),
+    (
+        "When to see a doctor about a flare",
+        ResourceCategory::MedicalAdvice,
+        "Seek medical advice promptly if eczema weeps, crusts or blisters, if you develop a fever, or if \
+         a flare does not improve after a week of your usual treatment, as these can be signs of infection.",
+    ),
+];
+
+// Demo content for fresh deployments. A populated canister is left untouched
+// unless force is set, in which case another copy of the samples is added.
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn seed_demo_data(force: bool) -> EczemaResult<u64> {
+    let caller = ic_cdk::caller();
+    if !is_admin(caller) {
+        return Err(EczemaError::Unauthorized);
+    }
+    if !force && STATE.with(|state| !state.borrow().resources.is_empty()) {
+        return Ok(0);
+    }
+
+    let mut created = 0;
+    for (title, category, description) in DEMO_RESOURCES {
+        let payload = CreateResourcePayload {
+            title: title.to_string(),
+            description: description.to_string(),
+            category,
+            publish_at: None,
+            source_url: None,
+            reading_level: None,
+            subcategory: None,
+        };
+        // Admin-tuned limits or banned words may reject a sample; skip it rather than fail the batch.
+        if validate_payload(&payload).is_ok() {
+            insert_resource(payload, caller);
+            created += 1;
+        }
+    }
+    Ok(created)
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn clone_resource(id: u64) -> EczemaResult<EczemaResource> {
+    let caller = ic_cdk::caller();
+    let source = find_visible(id)?;
+
+    let mut payload = payload_from_resource(&source);
+    // Shorten a title already near the limit so the suffix still fits.
+    const SUFFIX: &str = " (copy)";
+    let mut end = payload.title.len().min(MAX_TITLE_LENGTH - SUFFIX.len());
+    while !payload.title.is_char_boundary(end) {
+        end -= 1;
+    }
+    payload.title.truncate(end);
+    payload.title.push_str(SUFFIX);
+    create_as(payload, caller)
+}
+
+#[ic_cdk_macros::query]
+fn get_resource(id: u64) -> EczemaResult<EczemaResource> {
+    find_visible(id).map(masked)
+}
+
+// The stored, unmasked resource, if the caller may see it.
+fn find_visible(id: u64) -> EczemaResult<EczemaResource> {
+    let caller = ic_cdk::caller();
+    let now = get_timestamp();
+    STATE.with(|state| {
+        state
+            .borrow()
+            .resources
+            .get(&id)
+            .filter(|r| is_visible_to(r, caller, now))
+            .cloned()
+            .ok_or(EczemaError::NotFound)
+    })
+}
+
+// Lets clients revalidate a cached copy: None means their copy is current.
+#[ic_cdk_macros::query]
+fn get_resource_if_changed(id: u64, known_hash: u64) -> EczemaResult<Option<EczemaResource>> {
+    let resource = get_resource(id)?;
+    Ok((resource.content_hash != Some(known_hash)).then_some(resource))
+}
+
+/// Returns one slot per requested id, in request order, so clients can zip the
+/// result back onto their own list. Missing or hidden ids yield `None`.
+#[ic_cdk_macros::query]
+fn get_resources_ordered(ids: Vec<u64>) -> Vec<Option<EczemaResource>> {
+    let caller = ic_cdk::caller();
+    let now = get_timestamp();
+    STATE.with(|state| {
+        let state = state.borrow();
+        let resources = &state.resources;
+        ids.iter()
+            .map(|id| resources.get(id).filter(|r| is_visible_to(r, caller, now)).cloned().map(masked))
+            .collect()
+    })
+}
+
+// Unique viewers are exact until MAX_TRACKED_VIEWERS principals have been seen.
+// After that, views from unseen principals are counted as unique at the
+// resource's existing views-per-viewer ratio, so the estimate keeps its shape
+// without the set growing further. Anonymous callers are indistinguishable
+// from each other and only count towards view_count.
+fn record_view(resource: &mut EczemaResource, caller: Principal) {
+    resource.view_count += 1;
+    if caller == Principal::anonymous() {
+        return;
+    }
+
+    VIEWERS.with(|viewers| {
+        let mut viewers = viewers.borrow_mut();
+        let seen = viewers.entry(resource.id).or_default();
+        if seen.contains(&caller) {
+            return;
+        }
+        if seen.len() < MAX_TRACKED_VIEWERS {
+            seen.insert(caller);
+            resource.unique_viewers += 1;
+        } else {
+            let views_per_viewer = (resource.view_count / resource.unique_viewers.max(1)).max(1);
+            if resource.view_count.is_multiple_of(views_per_viewer) {
+                resource.unique_viewers += 1;
+            }
+        }
+    })
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn open_resource(id: u64) -> EczemaResult<EczemaResource> {
+    let caller = ic_cdk::caller();
+    let now = get_timestamp();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let resources = &mut state.resources;
+        let resource = resources
+            .get_mut(&id)
+            .filter(|r| is_visible_to(r, caller, now))
+            .ok_or(EczemaError::NotFound)?;
+        record_view(resource, caller);
+        Ok(masked(resource.clone()))
+    })
+}
+
+#[ic_cdk_macros::query]
+fn list_resources(page: usize) -> ListResponse {
+    let caller = ic_cdk::caller();
+    let now = get_timestamp();
+    let mut all: Vec<EczemaResource> = STATE.with(|state| {
+        state
+            .borrow()
+            .resources
+            .values()
+            .filter(|r| is_visible_to(r, caller, now))
+            .cloned()
+            .collect()
+    });
+    sort_resources(&mut all, DEFAULT_SORT.with(|s| *s.borrow()));
+    build_page(all.iter(), page)
+}
+
+// Ids on either side of a resource in the full visible list under `order`,
+// so a detail page can link to its previous and next resource.
+#[ic_cdk_macros::query]
+fn get_neighbors(id: u64, order: SortOrder) -> EczemaResult<(Option<u64>, Option<u64>)> {
+    let caller = ic_cdk::caller();
+    let now = get_timestamp();
+    STATE.with(|state| {
+        let state = state.borrow();
+        let current = state
+            .resources
+            .get(&id)
+            .filter(|r| is_visible_to(r, caller, now))
+            .ok_or(EczemaError::NotFound)?;
+
+        let mut previous: Option<&EczemaResource> = None;
+        let mut next: Option<&EczemaResource> = None;
+        for r in state.resources.values().filter(|r| r.id != id && is_visible_to(r, caller, now)) {
+            if compare_resources(r, current, order).is_lt() {
+                if previous.is_none_or(|p| compare_resources(r, p, order).is_gt()) {
+                    previous = Some(r);
+                }
+            } else if next.is_none_or(|n| compare_resources(r, n, order).is_lt()) {
+                next = Some(r);
+            }
+        }
+        Ok((previous.map(|r| r.id), next.map(|r| r.id)))
+    })
+}
+
+// A category narrows the candidates through its index before the remaining
+// filters run over what is left.
+#[ic_cdk_macros::query]
+fn query_resources(filter: ResourceQuery, page: usize) -> ListResponse {
+    let caller = ic_cdk::caller();
+    let now = get_timestamp();
+    let text = filter.text.as_deref().map(|t| t.trim().to_lowercase()).filter(|t| !t.is_empty());
+    let matches = |r: &EczemaResource| {
+        is_visible_to(r, caller, now)
+            && filter.author.is_none_or(|author| same_owner(r.created_by, author))
+            && filter.verified.is_none_or(|verified| r.verified == verified)
+            && filter.created_after.is_none_or(|after| r.created_at >= after)
+            && filter.created_before.is_none_or(|before| r.created_at < before)
+            && text.as_ref().is_none_or(|text| {
+                r.title.to_lowercase().contains(text) || r.description.text().to_lowercase().contains(text)
+            })
+    };
+
+    let mut matching: Vec<EczemaResource> = STATE.with(|state| {
+        let state = state.borrow();
+        let resources = &state.resources;
+        match filter.category {
+            Some(category) => state
+                .category_index
+                .range((category, 0)..=(category, u64::MAX))
+                .filter_map(|((_, id), _)| resources.get(&id))
+                .filter(|r| matches(r))
+                .cloned()
+                .collect(),
+            None => resources.values().filter(|r| matches(r)).cloned().collect(),
+        }
+    });
+    sort_resources(&mut matching, filter.sort.unwrap_or_else(|| DEFAULT_SORT.with(|s| *s.borrow())));
+    build_page(matching.iter(), page)
+}
+
+#[ic_cdk_macros::query]
+fn list_by_verification(filter: VerificationFilter, page: usize) -> ListResponse {
+    let caller = ic_cdk::caller();
+    let now = get_timestamp();
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut matching: Vec<&EczemaResource> = state
+            .resources
+            .values()
+            .filter(|r| match filter {
+                VerificationFilter::All => true,
+                VerificationFilter::VerifiedOnly => r.verified,
+                VerificationFilter::UnverifiedOnly => !r.verified,
+            })
+            .filter(|r| is_visible_to(r, caller, now))
+            .collect();
+        matching.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(b.id.cmp(&a.id)));
+        build_page(matching.into_iter(), page)
+    })
+}
+
+// Newest first by created_at, which admin_edit_resource can correct, so id
+// order alone isn't enough.
+#[ic_cdk_macros::query]
+fn latest_resources(limit: usize) -> Vec<EczemaResource> {
+    let now = get_timestamp();
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut latest: Vec<&EczemaResource> = state.resources.values().filter(|r| is_published(r, now)).collect();
+        latest.sort_unstable_by_key(|r| Reverse((r.created_at, r.id)));
+        latest.into_iter().take(limit.min(MAX_LATEST_RESOURCES)).cloned().map(masked).collect()
+    })
+}
+
+// day_unix can be any time within the UTC day. Oldest first within the day.
+#[ic_cdk_macros::query]
+fn resources_on_day(day_unix: u64, page: usize) -> Vec<EczemaResource> {
+    let start = day_unix - day_unix % SECONDS_PER_DAY;
+    let end = start.saturating_add(SECONDS_PER_DAY);
+    let caller = ic_cdk::caller();
+    let now = get_timestamp();
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut created: Vec<&EczemaResource> = state
+            .resources
+            .values()
+            .filter(|r| r.created_at >= start && r.created_at < end && is_visible_to(r, caller, now))
+            .collect();
+        created.sort_by_key(|r| (r.created_at, r.id));
+        created
+            .into_iter()
+            .skip(page * PAGE_SIZE)
+            .take(PAGE_SIZE)
+            .map(|r| masked(r.clone()))
+            .collect()
+    })
+}
+
+// (day start, resources created that day) for every UTC day from the one
+// containing `from` to the one containing `to`, zero days included, capped at
+// MAX_HISTOGRAM_DAYS. Only published resources count.
+#[ic_cdk_macros::query]
+fn creation_histogram(from: u64, to: u64) -> Vec<(u64, u64)> {
+    if to < from {
+        return Vec::new();
+    }
+    let first_day = from / SECONDS_PER_DAY;
+    let last_day = (to / SECONDS_PER_DAY).min(first_day + MAX_HISTOGRAM_DAYS - 1);
+    let now = get_timestamp();
+
+    let mut counts: BTreeMap<u64, u64> = (first_day..=last_day).map(|day| (day, 0)).collect();
+    STATE.with(|state| {
+        for r in state.borrow().resources.values().filter(|r| is_published(r, now)) {
+            if let Some(count) = counts.get_mut(&(r.created_at / SECONDS_PER_DAY)) {
+                *count += 1;
+            }
+        }
+    });
+    counts.into_iter().map(|(day, count)| (day * SECONDS_PER_DAY, count)).collect()
+}
+
+// (id, content hash) for every published, verified resource, sorted by id, so
+// an edge cache knows what it may serve anonymously and when it changed.
+#[ic_cdk_macros::query]
+fn cacheable_resource_ids() -> Vec<(u64, u64)> {
+    let now = get_timestamp();
+    let mut ids: Vec<(u64, u64)> = STATE.with(|state| {
+        state
+            .borrow()
+            .resources
+            .values()
+            .filter(|r| r.verified && is_published(r, now))
+            .map(|r| masked(r.clone()))
+            .map(|r| (r.id, r.content_hash.unwrap_or_else(|| content_hash(&r))))
+            .collect()
+    });
+    ids.sort_unstable_by_key(|&(id, _)| id);
+    ids
+}
+
+#[ic_cdk_macros::query]
+fn get_public_resource(id: u64) -> EczemaResult<PublicResource> {
+    get_resource(id).map(PublicResource::from)
+}
+
+#[ic_cdk_macros::query]
+fn list_public_resources(page: usize) -> Vec<PublicResource> {
+    list_resources(page).resources.into_iter().map(PublicResource::from).collect()
+}
+
+// Admin membership changes over time, so "official" is decided against the
+// current ADMINS list rather than stored on the resource.
+#[ic_cdk_macros::query]
+fn list_official_resources(page: usize) -> Vec<EczemaResource> {
+    let caller = ic_cdk::caller();
+    let now = get_timestamp();
+    let mut official: Vec<EczemaResource> = STATE.with(|state| {
+        state
+            .borrow()
+            .resources
+            .values()
+            .filter(|r| is_admin(r.created_by) && is_visible_to(r, caller, now))
+            .cloned()
+            .map(masked)
+            .collect()
+    });
+    sort_resources(&mut official, SortOrder::NewestFirst);
+    official.into_iter().skip(page * PAGE_SIZE).take(PAGE_SIZE).collect()
+}
+
+// Verified-but-unsourced content is the most misleading, so it is listed first.
+#[ic_cdk_macros::query]
+fn list_unsourced(page: usize) -> Vec<EczemaResource> {
+    let now = get_timestamp();
+    let mut unsourced: Vec<EczemaResource> = STATE.with(|state| {
+        state
+            .borrow()
+            .resources
+            .values()
+            .filter(|r| r.source_url.is_none() && is_published(r, now))
+            .cloned()
+            .map(masked)
+            .collect()
+    });
+    sort_resources(&mut unsourced, SortOrder::NewestFirst);
+    unsourced.sort_by_key(|r| !r.verified);
+    unsourced.into_iter().skip(page * PAGE_SIZE).take(PAGE_SIZE).collect()
+}
+
+#[ic_cdk_macros::query]
+fn list_my_resources(page: usize) -> Vec<EczemaResource> {
+    let caller = ic_cdk::caller();
+    let mut mine: Vec<EczemaResource> = STATE.with(|state| {
+        state
+            .borrow()
+            .resources
+            .values()
+            .filter(|r| same_owner(r.created_by, caller))
+            .cloned()
+            .map(masked)
+            .collect()
+    });
+    sort_resources(&mut mine, SortOrder::NewestFirst);
+    mine.into_iter().skip(page * PAGE_SIZE).take(PAGE_SIZE).collect()
+}
+
+#[ic_cdk_macros::query]
+fn get_total_created() -> u64 {
+    STATE.with(|state| state.borrow().total_created)
+}
+
+#[ic_cdk_macros::query]
+fn get_default_sort() -> SortOrder {
+    DEFAULT_SORT.with(|s| *s.borrow())
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn set_default_sort(order: SortOrder) -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    DEFAULT_SORT.with(|s| *s.borrow_mut() = order);
+    Ok(())
+}
+
+#[ic_cdk_macros::query]
+fn list_resources_by_category(category: ResourceCategory, page: usize) -> ListResponse {
+    let caller = ic_cdk::caller();
+    let now = get_timestamp();
+    STATE.with(|state| {
+        let state = state.borrow();
+        let resources = &state.resources;
+        let category_index = &state.category_index;
+        let matching = category_index
+            .range((category, 0)..=(category, u64::MAX))
+            .filter_map(|((_, id), _)| resources.get(&id))
+            .filter(|r| is_visible_to(r, caller, now));
+        build_page(matching, page)
+    })
+}
+
+#[ic_cdk_macros::query]
+fn list_by_subcategory(category: ResourceCategory, subcategory: String, page: usize) -> ListResponse {
+    let caller = ic_cdk::caller();
+    let now = get_timestamp();
+    STATE.with(|state| {
+        let state = state.borrow();
+        let resources = &state.resources;
+        let matching = state
+            .category_index
+            .range((category, 0)..=(category, u64::MAX))
+            .filter_map(|((_, id), _)| resources.get(&id))
+            .filter(|r| r.subcategory.as_ref() == Some(&subcategory) && is_visible_to(r, caller, now));
+        build_page(matching, page)
+    })
+}
+
+#[ic_cdk_macros::query]
+fn get_category_tree() -> Vec<(ResourceCategory, Vec<String>)> {
+    SUBCATEGORIES.with(|subcategories| {
+        let subcategories = subcategories.borrow();
+        ALL_CATEGORIES
+            .into_iter()
+            .map(|category| (category, subcategories.get(&category).cloned().unwrap_or_default()))
+            .collect()
+    })
+}
+
+// Replaces the category's subcategory list. One still used by a resource
+// can't be dropped, so no resource is left pointing at a missing subcategory.
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn set_subcategories(category: ResourceCategory, subcategories: Vec<String>) -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    let mut cleaned: Vec<String> = Vec::new();
+    for name in subcategories {
+        let name = name.trim().to_string();
+        if name.is_empty() || name.len() > MAX_SUBCATEGORY_LENGTH {
+            return Err(EczemaError::InvalidInput(format!(
+                "Subcategory names must be 1 to {} characters",
+                MAX_SUBCATEGORY_LENGTH
+            )));
+        }
+        if !cleaned.contains(&name) {
+            cleaned.push(name);
+        }
+    }
+    if cleaned.len() > MAX_SUBCATEGORIES {
+        return Err(EczemaError::InvalidInput(format!(
+            "A category can have at most {} subcategories",
+            MAX_SUBCATEGORIES
+        )));
+    }
+
+    let in_use = STATE.with(|state| {
+        let state = state.borrow();
+        state
+            .category_index
+            .range((category, 0)..=(category, u64::MAX))
+            .filter_map(|((_, id), _)| state.resources.get(&id).and_then(|r| r.subcategory.clone()))
+            .find(|subcategory| !cleaned.contains(subcategory))
+    });
+    if let Some(subcategory) = in_use {
+        return Err(EczemaError::InvalidInput(format!("Subcategory {} is still in use", subcategory)));
+    }
+
+    SUBCATEGORIES.with(|s| {
+        let mut s = s.borrow_mut();
+        if cleaned.is_empty() {
+            s.remove(&category);
+        } else {
+            s.insert(category, cleaned);
+        }
+    });
+    Ok(())
+}
+
+// Every category is listed, zero counts included, so a sidebar can grey out
+// empty ones. Like categories_in_use, only published resources count.
+#[ic_cdk_macros::query]
+fn all_category_counts() -> Vec<(ResourceCategory, u64)> {
+    let now = get_timestamp();
+    STATE.with(|state| {
+        let state = state.borrow();
+        let resources = &state.resources;
+        ALL_CATEGORIES
+            .into_iter()
+            .map(|category| {
+                let count = state
+                    .category_index
+                    .range((category, 0)..=(category, u64::MAX))
+                    .filter(|((_, id), _)| resources.get(id).is_some_and(|r| is_published(r, now)))
+                    .count();
+                (category, count as u64)
+            })
+            .collect()
+    })
+}
+
+// Scheduled resources that have not gone live yet don't count.
+#[ic_cdk_macros::query]
+fn categories_in_use() -> Vec<ResourceCategory> {
+    let now = get_timestamp();
+    STATE.with(|state| {
+        let state = state.borrow();
+        let resources = &state.resources;
+        let category_index = &state.category_index;
+        ALL_CATEGORIES
+            .into_iter()
+            .filter(|&category| {
+                category_index
+                    .range((category, 0)..=(category, u64::MAX))
+                    .any(|((_, id), _)| resources.get(&id).is_some_and(|r| is_published(r, now)))
+            })
+            .collect()
+    })
+}
+
+// One call for the whole homepage, each category's resources newest first as
+// in latest_resources.
+#[ic_cdk_macros::query]
+fn homepage_sections(per_category: usize) -> Vec<(ResourceCategory, Vec<EczemaResource>)> {
+    let per_category = per_category.min(MAX_SECTION_SIZE);
+    let now = get_timestamp();
+    STATE.with(|state| {
+        let state = state.borrow();
+        let resources = &state.resources;
+        let category_index = &state.category_index;
+        ALL_CATEGORIES
+            .into_iter()
+            .map(|category| {
+                let mut newest: Vec<&EczemaResource> = category_index
+                    .range((category, 0)..=(category, u64::MAX))
+                    .filter_map(|((_, id), _)| resources.get(&id))
+                    .filter(|r| is_published(r, now))
+                    .collect();
+                newest.sort_unstable_by_key(|r| Reverse((r.created_at, r.id)));
+                (category, newest.into_iter().take(per_category).cloned().map(masked).collect::<Vec<_>>())
+            })
+            .filter(|(_, newest)| !newest.is_empty())
+            .collect()
+    })
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn set_featured(id: u64, featured: bool) -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    if !STATE.with(|state| state.borrow().resources.contains_key(&id)) {
+        return Err(EczemaError::NotFound);
+    }
+    FEATURED.with(|f| {
+        let mut f = f.borrow_mut();
+        if featured {
+            f.insert(id);
+        } else {
+            f.remove(&id);
+        }
+    });
+    Ok(())
+}
+
+// Picks up to n featured resources without replacement, each draw weighted by
+// 1 / (1 + view_count) so less-seen items get a fair share of exposure. It is
+// an update call because randomness comes from raw_rand and every pick is
+// recorded as an impression.
+#[ic_cdk_macros::update(guard = "note_caller")]
+async fn sample_featured(n: usize) -> Vec<EczemaResource> {
+    let Ok((seed,)) = ic_cdk::api::management_canister::main::raw_rand().await else {
+        return Vec::new();
+    };
+    let now = get_timestamp();
+    let featured = FEATURED.with(|f| f.borrow().clone());
+    let mut candidates: Vec<(u64, EczemaResource)> = STATE.with(|state| {
+        let state = state.borrow();
+        featured
+            .iter()
+            .filter_map(|id| state.resources.get(id))
+            .filter(|r| is_published(r, now))
+            .map(|r| ((1_000_000 / (1 + r.view_count)).max(1), masked(r.clone())))
+            .collect()
+    });
+
+    // xorshift64 seeded from the 32 random bytes is plenty for picking a handful of items.
+    let mut rng = seed.iter().fold(0x9e37_79b9_7f4a_7c15u64, |acc, &b| acc.rotate_left(8) ^ u64::from(b));
+    let mut next_random = move || {
+        rng ^= rng << 13;
+        rng ^= rng >> 7;
+        rng ^= rng << 17;
+        rng
+    };
+
+    let mut picked = Vec::new();
+    while picked.len() < n.min(MAX_FEATURED_SAMPLE) && !candidates.is_empty() {
+        let total: u64 = candidates.iter().map(|(weight, _)| weight).sum();
+        let mut target = next_random() % total;
+        let index = candidates
+            .iter()
+            .position(|&(weight, _)| {
+                if target < weight {
+                    return true;
+                }
+                target -= weight;
+                false
+            })
+            .unwrap_or(0);
+        picked.push(candidates.swap_remove(index).1);
+    }
+
+    FEATURED_IMPRESSIONS.with(|impressions| {
+        let mut impressions = impressions.borrow_mut();
+        for resource in &picked {
+            *impressions.entry(resource.id).or_default() += 1;
+        }
+    });
+    picked
+}
+
+// (resource id, impressions, views) for every featured resource.
+#[ic_cdk_macros::query]
+fn featured_stats() -> EczemaResult<Vec<(u64, u64, u64)>> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    let featured = FEATURED.with(|f| f.borrow().clone());
+    Ok(STATE.with(|state| {
+        let state = state.borrow();
+        featured
+            .iter()
+            .filter_map(|id| state.resources.get(id))
+            .map(|r| {
+                let impressions = FEATURED_IMPRESSIONS.with(|i| i.borrow().get(&r.id).copied().unwrap_or_default());
+                (r.id, impressions, r.view_count)
+            })
+            .collect()
+    }))
+}
+
+// Only published content goes in, so one snapshot serves every caller.
+fn build_homepage_snapshot() -> HomepageSnapshot {
+    let now = get_timestamp();
+    let featured_ids = FEATURED.with(|f| f.borrow().clone());
+    let featured = STATE.with(|state| {
+        let state = state.borrow();
+        featured_ids
+            .iter()
+            .filter_map(|id| state.resources.get(id))
+            .filter(|r| is_published(r, now))
+            .cloned()
+            .map(masked)
+            .collect()
+    });
+    HomepageSnapshot {
+        generated_at: now,
+        sections: homepage_sections(HOMEPAGE_SECTION_SIZE),
+        latest: latest_resources(HOMEPAGE_LATEST_SIZE),
+        featured,
+    }
+}
+
+fn refresh_homepage_cache() {
+    let snapshot = build_homepage_snapshot();
+    HOMEPAGE_CACHE.with(|cache| *cache.borrow_mut() = Some((snapshot.generated_at, snapshot)));
+}
+
+// changes_since hides a scheduled resource's earlier events until it is due,
+// so a client whose cursor moved past them needs a Published event once it is.
+fn announce_due_resources() {
+    let now = get_timestamp();
+    let since = LAST_PUBLISH_SWEEP.with(|last| last.replace(now));
+    let due: Vec<u64> = STATE.with(|state| {
+        state
+            .borrow()
+            .resources
+            .values()
+            .filter(|r| r.publish_at.is_some_and(|t| t > since && t <= now) && is_published(r, now))
+            .map(|r| r.id)
+            .collect()
+    });
+    for id in due {
+        record_change(ChangeKind::Published, id, now);
+    }
+}
+
+fn start_publish_sweep() {
+    ic_cdk_timers::set_timer_interval(std::time::Duration::from_secs(PUBLISH_SWEEP_SECONDS), announce_due_resources);
+}
+
+fn start_homepage_refresh() {
+    refresh_homepage_cache();
+    ic_cdk_timers::set_timer_interval(std::time::Duration::from_secs(HOMEPAGE_CACHE_SECONDS), refresh_homepage_cache);
+}
+
+// Changes made during a query are discarded, so a stale cache can't be
+// replaced here; the timer started in init/post_upgrade does that. A cache
+// older than the refresh interval (e.g. the timer has not fired yet) is
+// bypassed and the snapshot computed for this call only.
+#[ic_cdk_macros::query]
+fn get_homepage() -> HomepageSnapshot {
+    let now = get_timestamp();
+    HOMEPAGE_CACHE
+        .with(|cache| {
+            cache
+                .borrow()
+                .as_ref()
+                .filter(|(computed_at, _)| now.saturating_sub(*computed_at) <= HOMEPAGE_CACHE_SECONDS)
+                .map(|(_, snapshot)| snapshot.clone())
+        })
+        .unwrap_or_else(build_homepage_snapshot)
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn invalidate_homepage_cache() -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    refresh_homepage_cache();
+    Ok(())
+}
+
+#[ic_cdk_macros::query]
+fn list_by_reading_level(level: ReadingLevel, page: usize) -> ListResponse {
+    let caller = ic_cdk::caller();
+    let now = get_timestamp();
+    STATE.with(|state| {
+        let state = state.borrow();
+        let matching = state
+            .reading_level_index
+            .range((level, 0)..=(level, u64::MAX))
+            .filter_map(|(_, id)| state.resources.get(id))
+            .filter(|r| is_visible_to(r, caller, now));
+        build_page(matching, page)
+    })
+}
+
+// Rough readability from average sentence length (in words) and average word
+// length (in letters); long sentences of long words read as clinical.
+#[ic_cdk_macros::query]
+fn suggest_reading_level(text: String) -> ReadingLevel {
+    let words: Vec<&str> = text
+        .split(|c: char| !c.is_alphanumeric() && c != '\'')
+        .filter(|w| !w.is_empty())
+        .collect();
+    if words.is_empty() {
+        return ReadingLevel::Basic;
+    }
+    let sentences = text
+        .split(['.', '!', '?'])
+        .filter(|s| s.chars().any(char::is_alphanumeric))
+        .count()
+        .max(1);
+    let words_per_sentence = words.len() as f64 / sentences as f64;
+    let letters_per_word = words.iter().map(|w| w.chars().count()).sum::<usize>() as f64 / words.len() as f64;
+
+    if words_per_sentence > 22.0 || letters_per_word > 5.8 {
+        ReadingLevel::Clinical
+    } else if words_per_sentence > 14.0 || letters_per_word > 4.8 {
+        ReadingLevel::Intermediate
+    } else {
+        ReadingLevel::Basic
+    }
+}
+
+#[ic_cdk_macros::query]
+fn autocomplete_titles(prefix: String, limit: usize) -> Vec<(u64, String)> {
+    let prefix = normalize_title(&prefix);
+    if prefix.is_empty() {
+        return Vec::new();
+    }
+
+    let caller = ic_cdk::caller();
+    let now = get_timestamp();
+    STATE.with(|state| {
+        let state = state.borrow();
+        let resources = &state.resources;
+        state
+            .title_index
+            .range((prefix.clone(), 0)..)
+            .take_while(|(title, _)| title.starts_with(&prefix))
+            .filter_map(|(_, id)| resources.get(id))
+            .filter(|r| is_visible_to(r, caller, now))
+            .take(limit.min(PAGE_SIZE))
+            .map(|r| (r.id, display_text(&r.title)))
+            .collect()
+    })
+}
+
+#[ic_cdk_macros::query]
+fn can_edit(id: u64) -> bool {
+    let caller = ic_cdk::caller();
+    STATE.with(|state| state.borrow().resources.get(&id).is_some_and(|r| may_edit(r, caller)))
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn acquire_edit_lock(id: u64) -> EczemaResult<u64> {
+    let caller = ic_cdk::caller();
+    let may = STATE.with(|state| state.borrow().resources.get(&id).map(|r| may_edit(r, caller)));
+    match may {
+        None => return Err(EczemaError::NotFound),
+        Some(false) => return Err(EczemaError::Unauthorized),
+        Some(true) => {}
+    }
+    let now = get_timestamp();
+    if locked_by_other(id, caller, now) {
+        return Err(EczemaError::Locked);
+    }
+
+    let token = NEXT_LOCK_TOKEN.with(|next| {
+        let token = *next.borrow();
+        *next.borrow_mut() += 1;
+        token
+    });
+    EDIT_LOCKS.with(|locks| {
+        locks.borrow_mut().insert(
+            id,
+            EditLock {
+                holder: caller,
+                token,
+                expires_at: now + EDIT_LOCK_SECONDS,
+            },
+        )
+    });
+    Ok(token)
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn release_edit_lock(id: u64, token: u64) -> EczemaResult<()> {
+    let caller = ic_cdk::caller();
+    EDIT_LOCKS.with(|locks| {
+        let mut locks = locks.borrow_mut();
+        match locks.get(&id) {
+            Some(lock) if lock.token == token && same_owner(lock.holder, caller) => {
+                locks.remove(&id);
+                Ok(())
+            }
+            Some(_) => Err(EczemaError::Unauthorized),
+            None => Err(EczemaError::NotFound),
+        }
+    })
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn lock_resource(id: u64) -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    if !STATE.with(|state| state.borrow().resources.contains_key(&id)) {
+        return Err(EczemaError::NotFound);
+    }
+    if !LOCKED_RESOURCES.with(|locked| locked.borrow_mut().insert(id)) {
+        return Err(EczemaError::AlreadyExists);
+    }
+    record_status(id, StatusChange::Locked, ic_cdk::caller());
+    note_moderated(id, get_timestamp());
+    Ok(())
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn unlock_resource(id: u64) -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    if !LOCKED_RESOURCES.with(|locked| locked.borrow_mut().remove(&id)) {
+        return Err(EczemaError::NotFound);
+    }
+    record_status(id, StatusChange::Unlocked, ic_cdk::caller());
+    Ok(())
+}
+
+#[ic_cdk_macros::query]
+fn is_resource_locked(id: u64) -> bool {
+    is_locked(id)
+}
+
+// Editing a verified resource always sets reverify_required on the returned
+// resource. With auto_unverify_on_edit on, it also drops the verification
+// itself, including who verified it and when.
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn update_resource(id: u64, payload: CreateResourcePayload) -> EczemaResult<EczemaResource> {
+    validate_payload(&payload)?;
+    let caller = ic_cdk::caller();
+
+    let now = get_timestamp();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let resource = state.resources.get(&id).ok_or(EczemaError::NotFound)?;
+        if !may_edit(resource, caller) {
+            return Err(EczemaError::Unauthorized);
+        }
+        if locked_by_other(id, caller, now) {
+            return Err(EczemaError::Locked);
+        }
+        if payload.category != resource.category {
+            check_category_access(payload.category, caller)?;
+        }
+        let was_verified = resource.verified;
+
+        let resource = state.replace_content(id, payload, now).ok_or(EczemaError::NotFound)?;
+        // The edit succeeds as far as the author can tell, but nobody else sees it.
+        if is_shadowbanned(caller) {
+            HIDDEN_RESOURCES.with(|h| h.borrow_mut().insert(id));
+        }
+        if was_verified && !resource.verified {
+            record_status(id, StatusChange::Unverified, caller);
+        }
+        notify_followers(id, NotificationKind::Updated);
+        Ok(masked(resource.clone()))
+    })
+}
+
+// For repairs such as a bad import. Content fields still go through
+// validation, but edit permissions, locks and the past-publish-time check
+// don't apply. A corrected created_at can put a resource out of id order, so
+// listings by age sort on (created_at, id) rather than on id alone.
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn admin_edit_resource(id: u64, patch: AdminResourcePatch) -> EczemaResult<EczemaResource> {
+    let caller = ic_cdk::caller();
+    if !is_admin(caller) {
+        return Err(EczemaError::Unauthorized);
+    }
+    let current = STATE.with(|state| state.borrow().resources.get(&id).cloned()).ok_or(EczemaError::NotFound)?;
+    let now = get_timestamp();
+
+    let content_changed = patch.title.is_some()
+        || patch.description.is_some()
+        || patch.category.is_some()
+        || patch.source_url.is_some()
+        || patch.reading_level.is_some()
+        || patch.subcategory.is_some();
+    let mut payload = payload_from_resource(&current);
+    if let Some(title) = patch.title {
+        payload.title = title;
+    }
+    if let Some(description) = patch.description {
+        payload.description = description;
+    }
+    if let Some(category) = patch.category.filter(|&c| c != current.category) {
+        payload.category = category;
+        payload.subcategory = None;
+    }
+    if let Some(source_url) = patch.source_url {
+        payload.source_url = source_url;
+    }
+    if let Some(reading_level) = patch.reading_level {
+        payload.reading_level = reading_level;
+    }
+    if let Some(subcategory) = patch.subcategory {
+        payload.subcategory = subcategory;
+    }
+    if content_changed {
+        validate_payload(&payload)?;
+    }
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if content_changed {
+            payload.publish_at = current.publish_at;
+            state.replace_content(id, payload, now);
+        }
+        let resource = state.resources.get_mut(&id).ok_or(EczemaError::NotFound)?;
+        if let Some(publish_at) = patch.publish_at {
+            resource.publish_at = publish_at;
+        }
+        if let Some(created_at) = patch.created_at {
+            resource.created_at = created_at;
+        }
+        if let Some(created_by) = patch.created_by {
+            resource.created_by = created_by;
+        }
+        match patch.verified {
+            Some(true) if !resource.verified => mark_verified(resource, caller, now),
+            Some(false) => {
+                resource.verified = false;
+                resource.verified_by = None;
+                resource.verified_at = None;
+            }
+            _ => {}
+        }
+        resource.updated_at = now;
+        refresh_content_hash(resource);
+        record_change(ChangeKind::AdminOverride, id, now);
+        Ok(masked(resource.clone()))
+    })
+}
+
+// Lets an external CMS sync idempotently: the same external id always maps to
+// the same resource, which is updated in place on every later sync.
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn upsert_by_external_id(external_id: String, payload: CreateResourcePayload) -> EczemaResult<EczemaResource> {
+    let caller = ic_cdk::caller();
+    if !is_admin(caller) {
+        return Err(EczemaError::Unauthorized);
+    }
+    let external_id = external_id.trim().to_string();
+    if external_id.is_empty() || external_id.len() > MAX_EXTERNAL_ID_LENGTH {
+        return Err(EczemaError::InvalidInput("Invalid external id length".to_string()));
+    }
+
+    if let Some(id) = STATE.with(|state| state.borrow().external_index.get(&external_id).copied()) {
+        return update_resource(id, payload);
+    }
+
+    let created = create_resource(payload)?;
+    STATE.with(|state| {
+        state
+            .borrow_mut()
+            .set_external_id(created.id, external_id)
+            .map(|r| masked(r.clone()))
+            .ok_or(EczemaError::InternalError)
+    })
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn delete_resource(id: u64) -> EczemaResult<()> {
+    let caller = ic_cdk::caller();
+    if !is_admin(caller) {
+        return Err(EczemaError::Unauthorized);
+    }
+
+    purge_resource(id, get_timestamp()).map(|_| ()).ok_or(EczemaError::NotFound)
+}
+
+// Removes a resource along with everything that references its id, so no
+// index or follower list is left pointing at a deleted resource.
+fn purge_resource(id: u64, now: u64) -> Option<EczemaResource> {
+    let resource = STATE.with(|state| state.borrow_mut().remove(id, now))?;
+
+    TOMBSTONES.with(|tombstones| {
+        let mut tombstones = tombstones.borrow_mut();
+        tombstones.push_back((id, now));
+        if tombstones.len() > MAX_TOMBSTONES {
+            tombstones.pop_front();
+        }
+    });
+    SUBSCRIPTIONS.with(|s| s.borrow_mut().remove(&id));
+    VIEWERS.with(|v| v.borrow_mut().remove(&id));
+    FLAGS.with(|f| f.borrow_mut().remove(&id));
+    LOCKED_RESOURCES.with(|l| l.borrow_mut().remove(&id));
+    FEATURED.with(|f| f.borrow_mut().remove(&id));
+    FEATURED_IMPRESSIONS.with(|i| i.borrow_mut().remove(&id));
+    HIDDEN_RESOURCES.with(|h| h.borrow_mut().remove(&id));
+    STATUS_HISTORY.with(|h| h.borrow_mut().remove(&id));
+    MODERATION_TIMES.with(|t| t.borrow_mut().remove(&id));
+    EDIT_LOCKS.with(|locks| locks.borrow_mut().remove(&id));
+    NOTIFICATIONS.with(|n| n.borrow_mut().retain(|n| n.resource_id != id));
+    LINKS.with(|links| {
+        let mut links = links.borrow_mut();
+        for other in links.remove(&id).unwrap_or_default() {
+            if let Some(linked) = links.get_mut(&other) {
+                linked.retain(|&x| x != id);
+                if linked.is_empty() {
+                    links.remove(&other);
+                }
+            }
+        }
+    });
+    COLLECTIONS.with(|collections| {
+        for collection in collections.borrow_mut().values_mut() {
+            collection.resource_ids.retain(|&x| x != id);
+        }
+    });
+    Some(resource)
+}
+
+// Links show up on both resources, so adding one takes edit rights on both
+// ends; otherwise anyone could attach their resource to someone else's page.
+// Either end's editor may remove a link.
+fn check_link_endpoints(from: u64, to: u64, both_ends: bool) -> EczemaResult<()> {
+    if from == to {
+        return Err(EczemaError::InvalidInput("A resource cannot link to itself".to_string()));
+    }
+    let caller = ic_cdk::caller();
+    STATE.with(|state| {
+        let state = state.borrow();
+        let resources = &state.resources;
+        let source = resources.get(&from).ok_or(EczemaError::NotFound)?;
+        let target = resources.get(&to).ok_or(EczemaError::NotFound)?;
+        let allowed = if both_ends {
+            may_edit(source, caller) && may_edit(target, caller)
+        } else {
+            may_edit(source, caller) || may_edit(target, caller)
+        };
+        if !allowed {
+            return Err(EczemaError::Unauthorized);
+        }
+        Ok(())
+    })
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn link_resources(from: u64, to: u64) -> EczemaResult<()> {
+    check_link_endpoints(from, to, true)?;
+    LINKS.with(|links| {
+        let mut links = links.borrow_mut();
+        if links.get(&from).is_some_and(|linked| linked.contains(&to)) {
+            return Err(EczemaError::AlreadyExists);
+        }
+        links.entry(from).or_default().push(to);
+        links.entry(to).or_default().push(from);
+        Ok(())
+    })
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn unlink_resources(from: u64, to: u64) -> EczemaResult<()> {
+    check_link_endpoints(from, to, false)?;
+    LINKS.with(|links| {
+        let mut links = links.borrow_mut();
+        if !links.get(&from).is_some_and(|linked| linked.contains(&to)) {
+            return Err(EczemaError::NotFound);
+        }
+        for (a, b) in [(from, to), (to, from)] {
+            if let Some(linked) = links.get_mut(&a) {
+                linked.retain(|&x| x != b);
+                if linked.is_empty() {
+                    links.remove(&a);
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+#[ic_cdk_macros::query]
+fn get_linked(id: u64) -> Vec<EczemaResource> {
+    let caller = ic_cdk::caller();
+    let now = get_timestamp();
+    let linked = LINKS.with(|links| links.borrow().get(&id).cloned().unwrap_or_default());
+    STATE.with(|state| {
+        let state = state.borrow();
+        let resources = &state.resources;
+        linked
+            .iter()
+            .filter_map(|id| resources.get(id))
+            .filter(|r| is_visible_to(r, caller, now))
+            .cloned()
+            .map(masked)
+            .collect()
+    })
+}
+
+// Lets off-chain mirrors apply deletions. Only the most recent deletions are
+// kept, so a client that falls further behind must resync in full.
+#[ic_cdk_macros::query]
+fn list_tombstones_since(timestamp: u64) -> Vec<(u64, u64)> {
+    TOMBSTONES.with(|tombstones| {
+        tombstones
+            .borrow()
+            .iter()
+            .filter(|(_, deleted_at)| *deleted_at >= timestamp)
+            .copied()
+            .collect()
+    })
+}
+
+// Events are numbered without gaps, so a client that asks for a seq older
+// than the first retained event knows it has missed changes and must resync.
+// Events for resources the caller can't see yet are left out of the response;
+// deletions always come through, since there is nothing left to check. A
+// Published event follows once such a resource goes live.
+#[ic_cdk_macros::query]
+fn changes_since(seq: u64, limit: usize) -> Vec<ChangeEvent> {
+    let caller = ic_cdk::caller();
+    let now = get_timestamp();
+    STATE.with(|state| {
+        let state = state.borrow();
+        CHANGELOG.with(|changelog| {
+            changelog
+                .borrow()
+                .iter()
+                .filter(|event| event.seq > seq)
+                .filter(|event| {
+                    state
+                        .resources
+                        .get(&event.resource_id)
+                        .is_none_or(|r| is_visible_to(r, caller, now))
+                })
+                .take(limit.min(MAX_CHANGES_PAGE))
+                .cloned()
+                .collect()
+        })
+    })
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn recategorize(from: ResourceCategory, to: ResourceCategory, only_unverified: bool) -> EczemaResult<u64> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    if from == to {
+        return Err(EczemaError::InvalidInput("Source and target categories are the same".to_string()));
+    }
+    let now = get_timestamp();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let ids: Vec<u64> = state
+            .category_index
+            .range((from, 0)..=(from, u64::MAX))
+            .map(|((_, id), _)| id)
+            .filter(|id| state.resources.get(id).is_some_and(|r| !(only_unverified && r.verified)))
+            .collect();
+
+        for &id in &ids {
+            state.move_to_category(id, to, now);
+        }
+        Ok(ids.len() as u64)
+    })
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn verify_resource(id: u64) -> EczemaResult<EczemaResource> {
+    let caller = ic_cdk::caller();
+    if !is_admin(caller) {
+        return Err(EczemaError::Unauthorized);
+    }
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let resources = &mut state.resources;
+        if let Some(resource) = resources.get_mut(&id) {
+            mark_verified(resource, caller, get_timestamp());
+            Ok(masked(resource.clone()))
+        } else {
+            Err(EczemaError::NotFound)
+        }
+    })
+}
+
+fn mark_verified(resource: &mut EczemaResource, verifier: Principal, now: u64) {
+    resource.verified = true;
+    resource.reverify_required = false;
+    resource.verified_by = Some(verifier);
+    resource.verified_at = Some(now);
+    resource.updated_at = now;
+    refresh_content_hash(resource);
+    record_change(ChangeKind::Verified, resource.id, now);
+    record_status(resource.id, StatusChange::Verified, verifier);
+    note_moderated(resource.id, now);
+    notify_followers(resource.id, NotificationKind::Verified);
+    notify_target_of_verification(resource);
+}
+
+// One-way message: no reply is awaited, so a slow or failing target can't
+// hold up or roll back the verification. Only send failures are visible here.
+fn notify_target_of_verification(resource: &EczemaResource) {
+    let Some(target) = NOTIFY_TARGET.with(|t| *t.borrow()) else {
+        return;
+    };
+    let args = (resource.id, display_text(&resource.title));
+    if let Err(code) = ic_cdk::notify(target, "on_resource_verified", args) {
+        ic_cdk::println!("on_resource_verified({}) to {} failed: {:?}", resource.id, target, code);
+    }
+}
+
+#[ic_cdk_macros::query]
+fn get_notify_target() -> Option<Principal> {
+    NOTIFY_TARGET.with(|t| *t.borrow())
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn set_notify_target(target: Option<Principal>) -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    NOTIFY_TARGET.with(|t| *t.borrow_mut() = target);
+    Ok(())
+}
+
+// Clears a trusted author's backlog in one call. Scheduled resources are left
+// for review once they go live.
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn verify_all_by_author(author: Principal) -> EczemaResult<u64> {
+    let caller = ic_cdk::caller();
+    if !is_admin(caller) {
+        return Err(EczemaError::Unauthorized);
+    }
+    let now = get_timestamp();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let mut verified = 0;
+        for resource in state.resources.values_mut() {
+            let eligible = is_due(resource, now) && !is_hidden(resource.id);
+            if !resource.verified && same_owner(resource.created_by, author) && eligible {
+                mark_verified(resource, caller, now);
+                verified += 1;
+            }
+        }
+        Ok(verified)
+    })
+}
+
+// Resources verified before verified_at was recorded count as verified but
+// are left out of the average.
+#[ic_cdk_macros::query]
+fn verification_stats() -> VerificationStats {
+    STATE.with(|state| {
+        let state = state.borrow();
+        let resources = &state.resources;
+        let total = resources.len() as u64;
+        let verified = resources.values().filter(|r| r.verified).count() as u64;
+        let delays: Vec<u64> = resources
+            .values()
+            .filter(|r| r.verified)
+            .filter_map(|r| r.verified_at.map(|at| at.saturating_sub(r.created_at)))
+            .collect();
+
+        VerificationStats {
+            total,
+            verified,
+            verified_percentage: if total == 0 { 0.0 } else { verified as f64 * 100.0 / total as f64 },
+            average_seconds_to_verify: if delays.is_empty() {
+                None
+            } else {
+                Some(delays.iter().sum::<u64>() / delays.len() as u64)
+            },
+        }
+    })
+}
+
+// For auditing one reviewer's decisions, most recently verified first.
+#[ic_cdk_macros::query]
+fn list_verified_by(admin: Principal, page: usize) -> Vec<EczemaResource> {
+    if role_of(ic_cdk::caller()) < Role::Moderator {
+        return Vec::new();
+    }
+    let mut verified: Vec<EczemaResource> = STATE.with(|state| {
+        state
+            .borrow()
+            .resources
+            .values()
+            .filter(|r| r.verified_by == Some(admin))
+            .cloned()
+            .map(masked)
+            .collect()
+    });
+    verified.sort_by(|a, b| b.verified_at.cmp(&a.verified_at).then(b.id.cmp(&a.id)));
+    verified.into_iter().skip(page * PAGE_SIZE).take(PAGE_SIZE).collect()
+}
+
+#[ic_cdk_macros::query]
+fn list_needs_reverification(page: usize) -> Vec<EczemaResource> {
+    if role_of(ic_cdk::caller()) < Role::Moderator {
+        return Vec::new();
+    }
+    let mut pending: Vec<EczemaResource> = STATE.with(|state| {
+        state
+            .borrow()
+            .resources
+            .values()
+            .filter(|r| r.reverify_required)
+            .cloned()
+            .map(masked)
+            .collect()
+    });
+    sort_resources(&mut pending, SortOrder::RecentlyUpdated);
+    pending.into_iter().skip(page * PAGE_SIZE).take(PAGE_SIZE).collect()
+}
+
+#[ic_cdk_macros::query]
+fn get_auto_unverify_on_edit() -> bool {
+    AUTO_UNVERIFY_ON_EDIT.with(|flag| *flag.borrow())
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn set_auto_unverify_on_edit(enabled: bool) -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    AUTO_UNVERIFY_ON_EDIT.with(|flag| *flag.borrow_mut() = enabled);
+    Ok(())
+}
+
+#[ic_cdk_macros::query]
+fn search_resources(query: String, page: usize) -> ListResponse {
+    let query = query.to_lowercase();
+    let caller = ic_cdk::caller();
+    let now = get_timestamp();
+    STATE.with(|state| {
+        let state = state.borrow();
+        let resources = &state.resources;
+        let matching = resources
+            .values()
+            .filter(|r| is_visible_to(r, caller, now))
+            .filter(|r| {
+                r.title.to_lowercase().contains(&query) ||
+                r.description.text().to_lowercase().contains(&query)
+            });
+        build_page(matching, page)
+    })
+}
+
+// Title matches count three times as much as description matches.
+fn search_score(resource: &EczemaResource, query: &str) -> u64 {
+    let title = resource.title.to_lowercase().matches(query).count() as u64;
+    let description = resource.description.text().to_lowercase().matches(query).count() as u64;
+    title * 3 + description
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+// Corrects each query word to the closest word appearing in a visible resource,
+// tolerating roughly one typo per three characters. Banned words are never
+// suggested, since with masking on they can still be in the stored text.
+fn suggest_query(query: &str, resources: &[&EczemaResource]) -> Option<String> {
+    let banned = BANNED_WORDS.with(|b| b.borrow().clone());
+    let mut vocabulary: BTreeSet<String> = BTreeSet::new();
+    for resource in resources {
+        for text in [resource.title.as_str(), &resource.description.text()] {
+            vocabulary.extend(
+                text.to_lowercase()
+                    .split(|c: char| !c.is_alphanumeric())
+                    .filter(|w| w.len() >= 3 && !banned.iter().any(|b| b == w))
+                    .map(str::to_string),
+            );
+        }
+    }
+
+    let mut changed = false;
+    let corrected: Vec<String> = query
+        .split_whitespace()
+        .map(|word| {
+            if vocabulary.contains(word) {
+                return word.to_string();
+            }
+            let max_distance = (word.chars().count() / 3).max(1);
+            match vocabulary
+                .iter()
+                .map(|term| (edit_distance(word, term), term))
+                .filter(|(distance, _)| *distance <= max_distance)
+                .min_by_key(|(distance, _)| *distance)
+            {
+                Some((_, term)) => {
+                    changed = true;
+                    term.clone()
+                }
+                None => word.to_string(),
+            }
+        })
+        .collect();
+
+    changed.then(|| corrected.join(" "))
+}
+
+#[ic_cdk_macros::query]
+fn search_ranked(query: String, page: usize) -> SearchResponse {
+    let query = query.trim().to_lowercase();
+    let caller = ic_cdk::caller();
+    let now = get_timestamp();
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        let visible: Vec<&EczemaResource> = state
+            .resources
+            .values()
+            .filter(|r| is_visible_to(r, caller, now))
+            .collect();
+        rank_search(&query, &visible, page)
+    })
+}
+
+// Scores each visible resource by the query terms it contains:
+//   sum over shared terms of (1 + ln(occurrences)) * ln(1 + resources / resources with the term)
+// so terms that appear everywhere count for little.
+#[ic_cdk_macros::query]
+fn recommend(text: String, limit: usize) -> Vec<(EczemaResource, f32)> {
+    let query: HashSet<String> = tokenize(&text).collect();
+    if query.is_empty() {
+        return Vec::new();
+    }
+    let caller = ic_cdk::caller();
+    let now = get_timestamp();
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        let corpus = state.resources.len() as f32;
+        let idf: HashMap<&String, f32> = query
+            .iter()
+            .filter_map(|term| {
+                let frequency = *state.document_frequency.get(term)?;
+                Some((term, (1.0 + corpus / frequency as f32).ln()))
+            })
+            .collect();
+        if idf.is_empty() {
+            return Vec::new();
+        }
+
+        let mut scored: Vec<(f32, &EczemaResource)> = state
+            .resources
+            .values()
+            .filter(|r| is_visible_to(r, caller, now))
+            .filter_map(|r| {
+                let mut occurrences: HashMap<String, u32> = HashMap::new();
+                for term in tokenize(&r.title).chain(tokenize(&r.description.text())) {
+                    if idf.contains_key(&term) {
+                        *occurrences.entry(term).or_default() += 1;
+                    }
+                }
+                let score: f32 = occurrences
+                    .iter()
+                    .map(|(term, &count)| (1.0 + (count as f32).ln()) * idf[term])
+                    .sum();
+                (score > 0.0).then_some((score, r))
+            })
+            .collect();
+        scored.sort_by(|(a_score, a), (b_score, b)| b_score.total_cmp(a_score).then(a.id.cmp(&b.id)));
+        scored
+            .into_iter()
+            .take(limit.min(MAX_RECOMMENDATIONS))
+            .map(|(score, r)| (masked(r.clone()), score))
+            .collect()
+    })
+}
+
+// Candidates come straight from the category's index bucket rather than a
+// scan over every resource.
+#[ic_cdk_macros::query]
+fn search_in_category(query: String, category: ResourceCategory, page: usize) -> SearchResponse {
+    let query = query.trim().to_lowercase();
+    let caller = ic_cdk::caller();
+    let now = get_timestamp();
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        let visible: Vec<&EczemaResource> = state
+            .category_index
+            .range((category, 0)..=(category, u64::MAX))
+            .filter_map(|((_, id), _)| state.resources.get(&id))
+            .filter(|r| is_visible_to(r, caller, now))
+            .collect();
+        rank_search(&query, &visible, page)
+    })
+}
+
+fn rank_search(query: &str, candidates: &[&EczemaResource], page: usize) -> SearchResponse {
+    let mut scored: Vec<(u64, &EczemaResource)> = candidates
+        .iter()
+        .map(|r| (search_score(r, query), *r))
+        .filter(|(score, _)| *score > 0)
+        .collect();
+    scored.sort_by(|(a_score, a), (b_score, b)| b_score.cmp(a_score).then(a.id.cmp(&b.id)));
+
+    let total_matches = scored.len() as u64;
+    let suggestion = if total_matches < SUGGESTION_THRESHOLD && !query.is_empty() {
+        suggest_query(query, candidates)
+    } else {
+        None
+    };
+
+    SearchResponse {
+        hits: scored
+            .into_iter()
+            .skip(page * PAGE_SIZE)
+            .take(PAGE_SIZE)
+            .map(|(score, r)| SearchHit {
+                resource: masked(r.clone()),
+                score,
+            })
+            .collect(),
+        total_matches,
+        suggestion,
+    }
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn subscribe(id: u64) -> EczemaResult<()> {
+    let caller = ic_cdk::caller();
+    if !STATE.with(|state| state.borrow().resources.contains_key(&id)) {
+        return Err(EczemaError::NotFound);
+    }
+
+    SUBSCRIPTIONS.with(|subscriptions| {
+        let mut subscriptions = subscriptions.borrow_mut();
+        let followers = subscriptions.entry(id).or_default();
+        if followers.contains(&caller) {
+            return Err(EczemaError::AlreadyExists);
+        }
+        followers.push(caller);
+        Ok(())
+    })
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn unsubscribe(id: u64) -> EczemaResult<()> {
+    let caller = ic_cdk::caller();
+
+    SUBSCRIPTIONS.with(|subscriptions| {
+        let mut subscriptions = subscriptions.borrow_mut();
+        let followers = subscriptions.get_mut(&id).ok_or(EczemaError::NotFound)?;
+        if !followers.contains(&caller) {
+            return Err(EczemaError::NotFound);
+        }
+        followers.retain(|&p| p != caller);
+        if followers.is_empty() {
+            subscriptions.remove(&id);
+        }
+        Ok(())
+    })
+}
+
+// Canisters can't push to clients, so callers poll this. It is an update call
+// because it advances the caller's cursor past everything it returns.
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn get_my_notifications() -> Vec<Notification> {
+    let caller = ic_cdk::caller();
+    let cursor = NOTIFICATION_CURSORS.with(|c| c.borrow().get(&caller).copied().unwrap_or(0));
+
+    let pending: Vec<Notification> = SUBSCRIPTIONS.with(|subscriptions| {
+        let subscriptions = subscriptions.borrow();
+        NOTIFICATIONS.with(|notifications| {
+            notifications
+                .borrow()
+                .iter()
+                .filter(|n| n.seq > cursor)
+                .filter(|n| subscriptions.get(&n.resource_id).is_some_and(|f| f.contains(&caller)))
+                .cloned()
+                .collect()
+        })
+    });
+
+    let latest_seq = NEXT_NOTIFICATION_SEQ.with(|n| *n.borrow()) - 1;
+    NOTIFICATION_CURSORS.with(|c| c.borrow_mut().insert(caller, latest_seq));
+    pending
+}
+
+// Each principal can flag a resource once.
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn flag_resource(id: u64, reason: FlagReason) -> EczemaResult<()> {
+    let caller = ic_cdk::caller();
+    if caller == Principal::anonymous() {
+        return Err(EczemaError::Unauthorized);
+    }
+    if let FlagReason::Other(note) = &reason {
+        if note.trim().is_empty() || note.len() > MAX_FLAG_NOTE_LENGTH {
+            return Err(EczemaError::InvalidInput("Invalid flag note length".to_string()));
+        }
+    }
+    get_resource(id)?;
+
+    FLAGS.with(|flags| {
+        let mut flags = flags.borrow_mut();
+        let entries = flags.entry(id).or_default();
+        if entries.iter().any(|flag| same_owner(flag.reporter, caller)) {
+            return Err(EczemaError::AlreadyExists);
+        }
+        entries.push(Flag {
+            reporter: caller,
+            reason,
+            created_at: get_timestamp(),
+        });
+        Ok(())
+    })?;
+    record_status(id, StatusChange::Flagged, caller);
+    MODERATION_TIMES.with(|times| {
+        times.borrow_mut().entry(id).or_insert(ModerationTimes {
+            first_flagged_at: get_timestamp(),
+            first_moderated_at: None,
+        });
+    });
+    Ok(())
+}
+
+// Response time runs from the first flag to the first verify or lock after it.
+#[ic_cdk_macros::query]
+fn moderation_sla() -> EczemaResult<SlaReport> {
+    if role_of(ic_cdk::caller()) < Role::Moderator {
+        return Err(EczemaError::Unauthorized);
+    }
+    let now = get_timestamp();
+    MODERATION_TIMES.with(|times| {
+        let times = times.borrow();
+        let responses: Vec<u64> = times
+            .values()
+            .filter_map(|t| t.first_moderated_at.map(|at| at.saturating_sub(t.first_flagged_at)))
+            .collect();
+        let oldest_pending = times
+            .values()
+            .filter(|t| t.first_moderated_at.is_none())
+            .map(|t| now.saturating_sub(t.first_flagged_at))
+            .max();
+
+        Ok(SlaReport {
+            flagged: times.len() as u64,
+            responded: responses.len() as u64,
+            average_response_seconds: if responses.is_empty() {
+                None
+            } else {
+                Some(responses.iter().sum::<u64>() / responses.len() as u64)
+            },
+            worst_response_seconds: responses.iter().max().copied(),
+            oldest_pending_seconds: oldest_pending,
+        })
+    })
+}
+
+// Moderator-only, since the actors include reporters and reviewers.
+#[ic_cdk_macros::query]
+fn get_status_history(id: u64) -> Vec<StatusEvent> {
+    if role_of(ic_cdk::caller()) < Role::Moderator {
+        return Vec::new();
+    }
+    STATUS_HISTORY.with(|history| history.borrow().get(&id).cloned().unwrap_or_default())
+}
+
+// Most common reason first. Free-text reasons are grouped by their exact text.
+#[ic_cdk_macros::query]
+fn flag_summary(id: u64) -> Vec<(FlagReason, u64)> {
+    if role_of(ic_cdk::caller()) < Role::Moderator {
+        return Vec::new();
+    }
+    let mut counts: BTreeMap<FlagReason, u64> = BTreeMap::new();
+    FLAGS.with(|flags| {
+        for flag in flags.borrow().get(&id).into_iter().flatten() {
+            *counts.entry(flag.reason.clone()).or_default() += 1;
+        }
+    });
+    let mut summary: Vec<(FlagReason, u64)> = counts.into_iter().collect();
+    summary.sort_by(|(a_reason, a), (b_reason, b)| b.cmp(a).then(a_reason.cmp(b_reason)));
+    summary
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn add_banned_word(word: String) -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    let word = word.trim().to_lowercase();
+    if word.is_empty() || !word.chars().all(char::is_alphanumeric) {
+        return Err(EczemaError::InvalidInput("Banned word must be a single word".to_string()));
+    }
+
+    BANNED_WORDS.with(|banned| {
+        let mut banned = banned.borrow_mut();
+        if banned.contains(&word) {
+            return Err(EczemaError::AlreadyExists);
+        }
+        banned.push(word);
+        Ok(())
+    })?;
+    refresh_homepage_cache();
+    Ok(())
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn remove_banned_word(word: String) -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    let word = word.trim().to_lowercase();
+
+    BANNED_WORDS.with(|banned| {
+        let mut banned = banned.borrow_mut();
+        let before = banned.len();
+        banned.retain(|w| *w != word);
+        if banned.len() == before {
+            return Err(EczemaError::NotFound);
+        }
+        Ok(())
+    })?;
+    refresh_homepage_cache();
+    Ok(())
+}
+
+#[ic_cdk_macros::query]
+fn list_banned_words() -> EczemaResult<Vec<String>> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    Ok(BANNED_WORDS.with(|banned| banned.borrow().clone()))
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn add_trusted_author(principal: Principal) -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    let principal = resolve_principal(principal);
+    TRUSTED_AUTHORS.with(|trusted| {
+        let mut trusted = trusted.borrow_mut();
+        if trusted.contains(&principal) {
+            return Err(EczemaError::AlreadyExists);
+        }
+        trusted.push(principal);
+        Ok(())
+    })
+}
+
+// Resources already auto-verified stay verified.
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn remove_trusted_author(principal: Principal) -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    let principal = resolve_principal(principal);
+    TRUSTED_AUTHORS.with(|trusted| {
+        let mut trusted = trusted.borrow_mut();
+        let before = trusted.len();
+        trusted.retain(|&p| p != principal);
+        if trusted.len() == before {
+            return Err(EczemaError::NotFound);
+        }
+        Ok(())
+    })
+}
+
+#[ic_cdk_macros::query]
+fn list_trusted_authors() -> EczemaResult<Vec<Principal>> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    Ok(TRUSTED_AUTHORS.with(|trusted| trusted.borrow().clone()))
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn set_announcement(message: String, expires_at: Option<u64>) -> EczemaResult<Announcement> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    if message.is_empty() || message.len() > MAX_ANNOUNCEMENT_LENGTH {
+        return Err(EczemaError::InvalidInput("Invalid announcement length".to_string()));
+    }
+    let now = get_timestamp();
+    if expires_at.is_some_and(|t| t <= now) {
+        return Err(EczemaError::InvalidInput("Expiry must be in the future".to_string()));
+    }
+
+    let announcement = Announcement {
+        message,
+        created_at: now,
+        expires_at,
+    };
+    ANNOUNCEMENT.with(|a| *a.borrow_mut() = Some(announcement.clone()));
+    Ok(announcement)
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn clear_announcement() -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    ANNOUNCEMENT.with(|a| *a.borrow_mut() = None);
+    Ok(())
+}
+
+#[ic_cdk_macros::query]
+fn get_announcement() -> Option<Announcement> {
+    let now = get_timestamp();
+    ANNOUNCEMENT.with(|a| {
+        a.borrow()
+            .clone()
+            .filter(|announcement| announcement.expires_at.is_none_or(|t| t > now))
+    })
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn set_role(principal: Principal, role: Role) -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    if role == Role::Admin {
+        return Err(EczemaError::InvalidInput("Admins are managed through the admin list".to_string()));
+    }
+    ROLES.with(|roles| {
+        let mut roles = roles.borrow_mut();
+        if role == Role::Contributor {
+            roles.remove(&principal);
+        } else {
+            roles.insert(principal, role);
+        }
+    });
+    Ok(())
+}
+
+// New resources from a shadowbanned principal are created as usual but
+// hidden from everyone else. Resources created before the ban stay visible
+// until the principal edits them, which hides them too.
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn shadowban(principal: Principal) -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    if is_admin(principal) {
+        return Err(EczemaError::InvalidInput("Admins cannot be shadowbanned".to_string()));
+    }
+    SHADOWBANNED.with(|s| s.borrow_mut().insert(resolve_principal(principal)));
+    Ok(())
+}
+
+fn authored_by(principal: Principal) -> Vec<u64> {
+    STATE.with(|state| {
+        state
+            .borrow()
+            .resources
+            .values()
+            .filter(|r| same_owner(r.created_by, principal))
+            .map(|r| r.id)
+            .collect()
+    })
+}
+
+// Lifting the ban also publishes whatever was hidden during it.
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn unshadowban(principal: Principal) -> EczemaResult<u64> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    if !SHADOWBANNED.with(|s| s.borrow_mut().remove(&resolve_principal(principal))) {
+        return Err(EczemaError::NotFound);
+    }
+    let authored = authored_by(principal);
+    let unhidden = HIDDEN_RESOURCES.with(|h| {
+        let mut h = h.borrow_mut();
+        authored.iter().filter(|id| h.remove(id)).count() as u64
+    });
+    // Sync clients skipped these while they were hidden, so tell them to look again.
+    let now = get_timestamp();
+    for &id in &authored {
+        record_change(ChangeKind::Updated, id, now);
+    }
+    Ok(unhidden)
+}
+
+// There is no separate remove_admin, so this also drops the principal's admin
+// rights. Verifications keep naming the departed admin unless restamped.
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn handle_admin_departure(
+    principal: Principal,
+    reassign_to: Principal,
+    restamp_verifications: bool,
+) -> EczemaResult<DepartureReport> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    if principal == reassign_to || reassign_to == Principal::anonymous() {
+        return Err(EczemaError::InvalidInput("Content must be reassigned to another principal".to_string()));
+    }
+    let admins = ADMINS.with(|a| a.borrow().clone());
+    if admins.iter().all(|&admin| admin == principal) {
+        return Err(EczemaError::InvalidInput("The last admin cannot be removed".to_string()));
+    }
+
+    let now = get_timestamp();
+    let (resources_reassigned, verifications_restamped) = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let mut reassigned = 0;
+        let mut restamped = 0;
+        for resource in state.resources.values_mut() {
+            let mut changed = false;
+            if resource.created_by == principal {
+                resource.created_by = reassign_to;
+                reassigned += 1;
+                changed = true;
+            }
+            if restamp_verifications && resource.verified_by == Some(principal) {
+                resource.verified_by = Some(reassign_to);
+                restamped += 1;
+                changed = true;
+            }
+            if changed {
+                record_change(ChangeKind::AdminOverride, resource.id, now);
+            }
+        }
+        (reassigned, restamped)
+    });
+    let collections_reassigned = COLLECTIONS.with(|collections| {
+        let mut reassigned = 0;
+        for collection in collections.borrow_mut().values_mut().filter(|c| c.owner == principal) {
+            collection.owner = reassign_to;
+            reassigned += 1;
+        }
+        reassigned
+    });
+    let admin_removed = admins.contains(&principal);
+    ADMINS.with(|a| a.borrow_mut().retain(|&admin| admin != principal));
+
+    Ok(DepartureReport {
+        resources_reassigned,
+        collections_reassigned,
+        verifications_restamped,
+        admin_removed,
+    })
+}
+
+#[ic_cdk_macros::query]
+fn get_my_role() -> Role {
+    role_of(ic_cdk::caller())
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn set_category_restriction(category: ResourceCategory, min_role: Option<Role>) -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    RESTRICTED_CATEGORIES.with(|restricted| {
+        let mut restricted = restricted.borrow_mut();
+        match min_role {
+            Some(role) => restricted.insert(category, role),
+            None => restricted.remove(&category),
+        }
+    });
+    Ok(())
+}
+
+#[ic_cdk_macros::query]
+fn list_category_restrictions() -> Vec<(ResourceCategory, Role)> {
+    let mut restrictions: Vec<(ResourceCategory, Role)> =
+        RESTRICTED_CATEGORIES.with(|r| r.borrow().iter().map(|(c, role)| (*c, *role)).collect());
+    restrictions.sort();
+    restrictions
+}
+
+#[ic_cdk_macros::query]
+fn list_verification_requirements() -> Vec<ResourceCategory> {
+    ALL_CATEGORIES.into_iter().filter(|&c| requires_verification(c)).collect()
+}
+
+// Authors and admins still see unverified resources in a gated category;
+// everyone else only sees them once verified.
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn set_requires_verification(category: ResourceCategory, required: bool) -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    let was_required = REQUIRES_VERIFICATION.with(|r| r.borrow_mut().insert(category, required)).unwrap_or_default();
+    if was_required && !required {
+        let now = get_timestamp();
+        let released: Vec<u64> = STATE.with(|state| {
+            state
+                .borrow()
+                .resources
+                .values()
+                .filter(|r| r.category == category && !r.verified && is_published(r, now))
+                .map(|r| r.id)
+                .collect()
+        });
+        for id in released {
+            record_change(ChangeKind::Published, id, now);
+        }
+    }
+    refresh_homepage_cache();
+    Ok(())
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn create_collection(title: String) -> EczemaResult<Collection> {
+    if title.is_empty() || title.len() > MAX_TITLE_LENGTH {
+        return Err(EczemaError::InvalidInput("Invalid title length".to_string()));
+    }
+    let caller = ic_cdk::caller();
+
+    NEXT_COLLECTION_ID.with(|next_id| {
+        COLLECTIONS.with(|collections| {
+            let id = *next_id.borrow();
+            let timestamp = get_timestamp();
+            let collection = Collection {
+                id,
+                title,
+                owner: caller,
+                resource_ids: Vec::new(),
+                created_at: timestamp,
+                updated_at: timestamp,
+            };
+            collections.borrow_mut().insert(id, collection.clone());
+            *next_id.borrow_mut() += 1;
+            Ok(collection)
+        })
+    })
+}
+
+fn with_owned_collection<F>(collection_id: u64, f: F) -> EczemaResult<Collection>
+where
+    F: FnOnce(&mut Collection) -> EczemaResult<()>,
+{
+    let caller = ic_cdk::caller();
+    COLLECTIONS.with(|collections| {
+        let mut collections = collections.borrow_mut();
+        let collection = collections.get_mut(&collection_id).ok_or(EczemaError::NotFound)?;
+        if !same_owner(collection.owner, caller) && !is_admin(caller) {
+            return Err(EczemaError::Unauthorized);
+        }
+        f(collection)?;
+        collection.updated_at = get_timestamp();
+        Ok(collection.clone())
+    })
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn add_to_collection(collection_id: u64, resource_id: u64) -> EczemaResult<Collection> {
+    if !STATE.with(|state| state.borrow().resources.contains_key(&resource_id)) {
+        return Err(EczemaError::NotFound);
+    }
+
+    with_owned_collection(collection_id, |collection| {
+        if collection.resource_ids.contains(&resource_id) {
+            return Err(EczemaError::AlreadyExists);
+        }
+        if collection.resource_ids.len() >= MAX_COLLECTION_SIZE {
+            return Err(EczemaError::InvalidInput("Collection is full".to_string()));
+        }
+        collection.resource_ids.push(resource_id);
+        Ok(())
+    })
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn remove_from_collection(collection_id: u64, resource_id: u64) -> EczemaResult<Collection> {
+    with_owned_collection(collection_id, |collection| {
+        if !collection.resource_ids.contains(&resource_id) {
+            return Err(EczemaError::NotFound);
+        }
+        collection.resource_ids.retain(|&x| x != resource_id);
+        Ok(())
+    })
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn reorder_collection(collection_id: u64, resource_ids: Vec<u64>) -> EczemaResult<Collection> {
+    with_owned_collection(collection_id, |collection| {
+        let mut current = collection.resource_ids.clone();
+        let mut requested = resource_ids.clone();
+        current.sort_unstable();
+        requested.sort_unstable();
+        if current != requested {
+            return Err(EczemaError::InvalidInput(
+                "New order must contain exactly the collection's current resources".to_string(),
+            ));
+        }
+        collection.resource_ids = resource_ids;
+        Ok(())
+    })
+}
+
+#[ic_cdk_macros::query]
+fn get_collection(id: u64) -> EczemaResult<CollectionDetail> {
+    let collection = COLLECTIONS.with(|c| c.borrow().get(&id).cloned()).ok_or(EczemaError::NotFound)?;
+    let caller = ic_cdk::caller();
+    let now = get_timestamp();
+
+    let resources = STATE.with(|state| {
+        let state = state.borrow();
+        let resources = &state.resources;
+        collection
+            .resource_ids
+            .iter()
+            .filter_map(|id| resources.get(id))
+            .filter(|r| is_visible_to(r, caller, now))
+            .cloned()
+            .map(masked)
+            .collect()
+    });
+    Ok(CollectionDetail { collection, resources })
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn add_template(payload: TemplatePayload) -> EczemaResult<Template> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    if payload.name.is_empty() || payload.name.len() > MAX_TITLE_LENGTH {
+        return Err(EczemaError::InvalidInput("Invalid template name length".to_string()));
+    }
+    validate_payload(&CreateResourcePayload {
+        title: payload.title.clone(),
+        description: payload.description.clone(),
+        category: payload.category,
+        publish_at: None,
+        source_url: None,
+        reading_level: None,
+        subcategory: None,
+    })?;
+
+    NEXT_TEMPLATE_ID.with(|next_id| {
+        TEMPLATES.with(|templates| {
+            let id = *next_id.borrow();
+            let template = Template {
+                id,
+                name: payload.name,
+                title: payload.title,
+                description: payload.description,
+                category: payload.category,
+            };
+            templates.borrow_mut().insert(id, template.clone());
+            *next_id.borrow_mut() += 1;
+            Ok(template)
+        })
+    })
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn remove_template(id: u64) -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    TEMPLATES.with(|templates| templates.borrow_mut().remove(&id).map(|_| ()).ok_or(EczemaError::NotFound))
+}
+
+#[ic_cdk_macros::query]
+fn list_templates() -> Vec<Template> {
+    let mut templates: Vec<Template> = TEMPLATES.with(|t| t.borrow().values().cloned().collect());
+    templates.sort_by_key(|t| t.id);
+    templates
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn create_from_template(template_id: u64, overrides: TemplateOverrides) -> EczemaResult<EczemaResource> {
+    let template = TEMPLATES
+        .with(|t| t.borrow().get(&template_id).cloned())
+        .ok_or(EczemaError::NotFound)?;
+
+    let payload = CreateResourcePayload {
+        title: overrides.title.unwrap_or(template.title),
+        description: overrides.description.unwrap_or(template.description),
+        category: overrides.category.unwrap_or(template.category),
+        publish_at: overrides.publish_at,
+        source_url: overrides.source_url,
+        reading_level: overrides.reading_level,
+        subcategory: overrides.subcategory,
+    };
+    create_resource(payload)
+}
+
+fn has_linked_principals(principal: Principal) -> bool {
+    PRINCIPAL_LINKS.with(|links| links.borrow().values().any(|&p| p == principal))
+}
+
+// Step one of linking: the primary names the principal it wants to absorb.
+// The link only takes effect once that principal calls confirm_link.
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn link_principal(other: Principal) -> EczemaResult<()> {
+    let caller = ic_cdk::caller();
+    if other == caller || other == Principal::anonymous() || caller == Principal::anonymous() {
+        return Err(EczemaError::InvalidInput("Cannot link this principal".to_string()));
+    }
+    if resolve_principal(caller) != caller {
+        return Err(EczemaError::InvalidInput("Linked principals cannot act as a primary".to_string()));
+    }
+    if resolve_principal(other) != other || has_linked_principals(other) {
+        return Err(EczemaError::AlreadyExists);
+    }
+    PENDING_LINKS.with(|pending| pending.borrow_mut().insert(other, caller));
+    Ok(())
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn confirm_link(primary: Principal) -> EczemaResult<()> {
+    let caller = ic_cdk::caller();
+    let requested = PENDING_LINKS.with(|pending| pending.borrow().get(&caller).copied());
+    if requested != Some(primary) {
+        return Err(EczemaError::NotFound);
+    }
+    if resolve_principal(primary) != primary || has_linked_principals(caller) {
+        return Err(EczemaError::InvalidInput("Link request is no longer valid".to_string()));
+    }
+    PENDING_LINKS.with(|pending| pending.borrow_mut().remove(&caller));
+    PRINCIPAL_LINKS.with(|links| links.borrow_mut().insert(caller, primary));
+    Ok(())
+}
+
+// Either side of a link can dissolve it.
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn unlink_principal(other: Principal) -> EczemaResult<()> {
+    let caller = ic_cdk::caller();
+    PRINCIPAL_LINKS.with(|links| {
+        let mut links = links.borrow_mut();
+        if links.get(&other) == Some(&caller) {
+            links.remove(&other);
+            Ok(())
+        } else if links.get(&caller) == Some(&other) {
+            links.remove(&caller);
+            Ok(())
+        } else {
+            Err(EczemaError::NotFound)
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn get_linked_principals() -> Vec<Principal> {
+    let primary = resolve_principal(ic_cdk::caller());
+    let mut linked: Vec<Principal> = PRINCIPAL_LINKS.with(|links| {
+        links
+            .borrow()
+            .iter()
+            .filter(|(_, p)| **p == primary)
+            .map(|(linked, _)| *linked)
+            .collect()
+    });
+    linked.sort();
+    linked.insert(0, primary);
+    linked
+}
+
+// For channels that can't render markdown, such as SMS.
+#[ic_cdk_macros::query]
+fn get_resource_plaintext(id: u64) -> EczemaResult<String> {
+    let resource = get_resource(id)?;
+    Ok(strip_markdown(&resource.description.text()))
+}
+
+// Handles the basics only: heading, quote and list markers at the start of a
+// line, emphasis and code markers, and links and images reduced to their text.
+fn strip_markdown(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            let mut line = line.trim_start();
+            if line.starts_with('#') {
+                line = line.trim_start_matches('#').trim_start();
+            }
+            if let Some(rest) = line.strip_prefix('>') {
+                line = rest.trim_start();
+            }
+            for marker in ["- ", "* ", "+ "] {
+                if let Some(rest) = line.strip_prefix(marker) {
+                    line = rest;
+                    break;
+                }
+            }
+            strip_inline_markdown(line)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn strip_inline_markdown(line: &str) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let mut out = String::with_capacity(line.len());
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '!' if chars.get(i + 1) == Some(&'[') => i += 1,
+            '[' => {
+                let close = chars[i..].iter().position(|&c| c == ']').map(|p| i + p);
+                match close {
+                    Some(close) if chars.get(close + 1) == Some(&'(') => {
+                        out.extend(&chars[i + 1..close]);
+                        let end = chars[close..].iter().position(|&c| c == ')').map(|p| close + p);
+                        i = end.map_or(chars.len(), |end| end + 1);
+                    }
+                    _ => {
+                        out.push('[');
+                        i += 1;
+                    }
+                }
+            }
+            '*' | '`' | '~' => i += 1,
+            // Underscores inside a word, as in snake_case, are kept.
+            '_' => {
+                let inside_word = i > 0
+                    && chars[i - 1].is_alphanumeric()
+                    && chars.get(i + 1).is_some_and(|c| c.is_alphanumeric());
+                if inside_word {
+                    out.push('_');
+                }
+                i += 1;
+            }
+            c => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+#[ic_cdk_macros::query]
+fn export_resource(id: u64) -> EczemaResult<String> {
+    let resource = get_resource(id)?;
+    let followers = SUBSCRIPTIONS.with(|s| s.borrow().get(&id).map_or(0, |f| f.len()));
+    let mut collections: Vec<u64> = COLLECTIONS.with(|c| {
+        c.borrow()
+            .values()
+            .filter(|collection| collection.resource_ids.contains(&id))
+            .map(|collection| collection.id)
+            .collect()
+    });
+    collections.sort_unstable();
+
+    let document = serde_json::json!({
+        "resource": resource,
+        "followers": followers,
+        "collections": collections,
+        "exported_at": get_timestamp(),
+    });
+    serde_json::to_string(&document).map_err(|_| EczemaError::InternalError)
+}
+
+#[ic_cdk_macros::query]
+fn get_category_limits() -> Vec<(ResourceCategory, CategoryLimits)> {
+    ALL_CATEGORIES.iter().map(|&c| (c, category_limits(c))).collect()
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn set_category_limits(category: ResourceCategory, limits: CategoryLimits) -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    if limits.min_description_length == 0
+        || limits.min_description_length > limits.max_description_length
+        || limits.max_description_length > MAX_DESCRIPTION_LENGTH as u64
+    {
+        return Err(EczemaError::InvalidInput(format!(
+            "Limits must satisfy 1 <= min <= max <= {MAX_DESCRIPTION_LENGTH}"
+        )));
+    }
+    CATEGORY_LIMITS.with(|l| l.borrow_mut().insert(category, limits));
+    Ok(())
 }
 
-#[derive(CandidType, Serialize, Deserialize)]
-pub struct CreateResourcePayload {
-    title: String,
-    description: String,
-    category: ResourceCategory,
+fn count_links(text: &str) -> u32 {
+    let text = text.to_lowercase();
+    (text.matches("http://").count() + text.matches("https://").count() + text.matches("www.").count()) as u32
 }
 
-#[derive(CandidType, Serialize, Deserialize)]
-pub enum EczemaError {
-    NotFound,
-    AlreadyExists,
-    InvalidInput(String),
-    Unauthorized,
-    InternalError,
+// Heuristic score from 0 to 100; each signal contributes at most:
+//   30 shouting (over half the letters capitalised), 20 long runs of one character,
+//   25 links in the title, 25 several links at over one per 50 description words,
+//   20 near-misses of banned words (exact matches are rejected outright).
+#[ic_cdk_macros::query]
+fn spam_score(payload: CreateResourcePayload) -> u32 {
+    let text = format!("{} {}", payload.title, payload.description);
+    let mut score = 0;
+
+    let letters = text.chars().filter(|c| c.is_alphabetic()).count();
+    let upper = text.chars().filter(|c| c.is_uppercase()).count();
+    if letters >= 10 && upper * 2 > letters {
+        score += 30;
+    }
+
+    let chars: Vec<char> = text.chars().collect();
+    let runs = chars
+        .chunk_by(|a, b| a == b)
+        .filter(|run| run.len() >= 4 && !run[0].is_whitespace())
+        .count() as u32;
+    score += (runs * 10).min(20);
+
+    if count_links(&payload.title) > 0 {
+        score += 25;
+    }
+    let words = payload.description.split_whitespace().count().max(1) as u32;
+    let links = count_links(&payload.description);
+    if links >= 2 && links * 50 > words {
+        score += 25;
+    }
+
+    let near_misses = BANNED_WORDS.with(|banned| {
+        let banned = banned.borrow();
+        text.to_lowercase()
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| word.len() >= 4)
+            .filter(|word| banned.iter().any(|b| b != word && edit_distance(word, b) == 1))
+            .count() as u32
+    });
+    score += (near_misses * 10).min(20);
+
+    score.min(100)
 }
 
-type EczemaResult<T> = Result<T, EczemaError>;
+#[ic_cdk_macros::query]
+fn get_mask_banned() -> bool {
+    MASK_BANNED.with(|m| *m.borrow())
+}
 
-thread_local! {
-    static ECZEMA_RESOURCES: RefCell<HashMap<u64, EczemaResource>> = RefCell::new(HashMap::new());
-    static CATEGORY_INDEX: RefCell<BTreeMap<ResourceCategory, Vec<u64>>> = RefCell::new(BTreeMap::new());
-    static NEXT_ID: RefCell<u64> = RefCell::new(1);
-    static ADMINS: RefCell<Vec<Principal>> = RefCell::new(Vec::new());
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn set_mask_banned(enabled: bool) -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    MASK_BANNED.with(|m| *m.borrow_mut() = enabled);
+    refresh_homepage_cache();
+    Ok(())
 }
 
-fn get_timestamp() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs()
+#[ic_cdk_macros::query]
+fn get_policy_status() -> PolicyStatus {
+    PolicyStatus {
+        current_version: POLICY_VERSION.with(|v| *v.borrow()),
+        accepted_version: accepted_policy_version(ic_cdk::caller()),
+    }
 }
 
-fn validate_payload(payload: &CreateResourcePayload) -> EczemaResult<()> {
-    if payload.title.is_empty() || payload.title.len() > MAX_TITLE_LENGTH {
-        return Err(EczemaError::InvalidInput("Invalid title length".to_string()));
+// Taking the version makes the caller confirm the policy they actually read,
+// not one published after their page loaded.
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn accept_policy(version: u32) -> EczemaResult<()> {
+    let current = POLICY_VERSION.with(|v| *v.borrow());
+    if version != current {
+        return Err(EczemaError::InvalidInput(format!("The current policy version is {}", current)));
+    }
+    ACKED_POLICY.with(|acked| acked.borrow_mut().insert(ic_cdk::caller(), version));
+    Ok(())
+}
+
+// Every contributor has to accept the new version before posting again.
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn bump_policy_version() -> EczemaResult<u32> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
     }
-    if payload.description.is_empty() || payload.description.len() > MAX_DESCRIPTION_LENGTH {
-        return Err(EczemaError::InvalidInput("Invalid description length".to_string()));
+    Ok(POLICY_VERSION.with(|v| {
+        let mut v = v.borrow_mut();
+        *v += 1;
+        *v
+    }))
+}
+
+#[ic_cdk_macros::query]
+fn get_new_account_cooldown() -> u64 {
+    NEW_ACCOUNT_COOLDOWN.with(|c| *c.borrow())
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn set_new_account_cooldown(seconds: u64) -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
     }
+    NEW_ACCOUNT_COOLDOWN.with(|c| *c.borrow_mut() = seconds);
     Ok(())
 }
 
-fn is_admin(caller: Principal) -> bool {
-    ADMINS.with(|admins| admins.borrow().contains(&caller))
+#[ic_cdk_macros::query]
+fn get_spam_threshold() -> Option<u32> {
+    SPAM_THRESHOLD.with(|t| *t.borrow())
 }
 
-#[ic_cdk_macros::update]
-fn create_resource(payload: CreateResourcePayload) -> EczemaResult<EczemaResource> {
-    validate_payload(&payload)?;
-    let caller = ic_cdk::caller();
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn set_spam_threshold(threshold: Option<u32>) -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    SPAM_THRESHOLD.with(|t| *t.borrow_mut() = threshold);
+    Ok(())
+}
 
-    NEXT_ID.with(|next_id| {
-        ECZEMA_RESOURCES.with(|resources| {
-            CATEGORY_INDEX.with(|category_index| {
-                let id = *next_id.borrow();
-                let timestamp = get_timestamp();
-
-                let resource = EczemaResource {
-                    id,
-                    title: payload.title,
-                    description: payload.description,
-                    category: payload.category,
-                    created_at: timestamp,
-                    updated_at: timestamp,
-                    verified: false,
-                    created_by: caller,
-                };
+#[ic_cdk_macros::query]
+fn get_disclaimer_text() -> String {
+    DISCLAIMER_TEXT.with(|text| text.borrow().clone())
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn set_disclaimer_text(text: String) -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    let text = text.trim().to_string();
+    if text.is_empty() || text.len() > MAX_DISCLAIMER_LENGTH {
+        return Err(EczemaError::InvalidInput("Invalid disclaimer length".to_string()));
+    }
+    DISCLAIMER_TEXT.with(|t| *t.borrow_mut() = text);
+    Ok(())
+}
+
+// Helpfulness blends the engagement signals the canister tracks:
+//   5 per follower + 3 per collection that includes the resource + 1 per view.
+// Following and curating are deliberate endorsements, so they outweigh a view.
+const HELPFUL_FOLLOWER_WEIGHT: u64 = 5;
+const HELPFUL_COLLECTION_WEIGHT: u64 = 3;
+const HELPFUL_VIEW_WEIGHT: u64 = 1;
+
+#[derive(Default, Clone, Copy)]
+struct Endorsements {
+    followers: u64,
+    collections: u64,
+}
+
+// Shared by helpfulness_scores and list_ranked so both count endorsements the same way.
+fn endorsement_counts() -> HashMap<u64, Endorsements> {
+    let mut counts: HashMap<u64, Endorsements> = SUBSCRIPTIONS.with(|subscriptions| {
+        subscriptions
+            .borrow()
+            .iter()
+            .map(|(&id, followers)| (id, Endorsements { followers: followers.len() as u64, collections: 0 }))
+            .collect()
+    });
+    COLLECTIONS.with(|collections| {
+        for collection in collections.borrow().values() {
+            for &id in &collection.resource_ids {
+                counts.entry(id).or_default().collections += 1;
+            }
+        }
+    });
+    counts
+}
 
-                resources.borrow_mut().insert(id, resource.clone());
-                category_index.borrow_mut().entry(payload.category).or_default().push(id);
-                *next_id.borrow_mut() += 1;
-                Ok(resource)
+fn helpfulness_scores() -> HashMap<u64, u64> {
+    let endorsements = endorsement_counts();
+    STATE.with(|state| {
+        state
+            .borrow()
+            .resources
+            .values()
+            .map(|r| {
+                let endorsed = endorsements.get(&r.id).copied().unwrap_or_default();
+                let score = r
+                    .view_count
+                    .saturating_mul(HELPFUL_VIEW_WEIGHT)
+                    .saturating_add(endorsed.followers.saturating_mul(HELPFUL_FOLLOWER_WEIGHT))
+                    .saturating_add(endorsed.collections.saturating_mul(HELPFUL_COLLECTION_WEIGHT));
+                (r.id, score)
             })
-        })
+            .collect()
     })
 }
 
+// recency * (100 points, halving every RECENCY_HALF_LIFE_DAYS)
+//   + endorsements * (followers + collections) + views * views + verified_bonus.
 #[ic_cdk_macros::query]
-fn get_resource(id: u64) -> EczemaResult<EczemaResource> {
-    ECZEMA_RESOURCES.with(|resources| {
-        resources
-            .borrow()
-            .get(&id)
-            .cloned()
-            .ok_or(EczemaError::NotFound)
+fn list_ranked(page: usize) -> ListResponse {
+    let caller = ic_cdk::caller();
+    let now = get_timestamp();
+    let weights = RANKING_WEIGHTS.with(|w| *w.borrow());
+
+    let endorsements = endorsement_counts();
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut ranked: Vec<(u64, &EczemaResource)> = state
+            .resources
+            .values()
+            .filter(|r| is_visible_to(r, caller, now))
+            .map(|r| {
+                let age_days = now.saturating_sub(r.created_at) / SECONDS_PER_DAY;
+                let recency = 100 * RECENCY_HALF_LIFE_DAYS / (RECENCY_HALF_LIFE_DAYS + age_days);
+                let endorsed = endorsements
+                    .get(&r.id)
+                    .map_or(0, |e| e.followers.saturating_add(e.collections));
+                let score = weights
+                    .recency
+                    .saturating_mul(recency)
+                    .saturating_add(weights.endorsements.saturating_mul(endorsed))
+                    .saturating_add(weights.views.saturating_mul(r.view_count))
+                    .saturating_add(if r.verified { weights.verified_bonus } else { 0 });
+                (score, r)
+            })
+            .collect();
+        ranked.sort_by(|(a_score, a), (b_score, b)| b_score.cmp(a_score).then(a.id.cmp(&b.id)));
+        build_page(ranked.into_iter().map(|(_, r)| r), page)
     })
 }
 
 #[ic_cdk_macros::query]
-fn list_resources(page: usize) -> Vec<EczemaResource> {
-    ECZEMA_RESOURCES.with(|resources| {
-        resources
-            .borrow()
+fn get_ranking_weights() -> RankingWeights {
+    RANKING_WEIGHTS.with(|w| *w.borrow())
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn set_ranking_weights(weights: RankingWeights) -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    RANKING_WEIGHTS.with(|w| *w.borrow_mut() = weights);
+    Ok(())
+}
+
+// Oldest first, so long-ignored content surfaces before anything just
+// published. With no votes to check, "no engagement" means nobody follows it.
+#[ic_cdk_macros::query]
+fn list_neglected(max_views: u64, page: usize) -> Vec<EczemaResource> {
+    let now = get_timestamp();
+    let followed: HashSet<u64> = SUBSCRIPTIONS.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, followers)| !followers.is_empty())
+            .map(|(&id, _)| id)
+            .collect()
+    });
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut neglected: Vec<&EczemaResource> = state
+            .resources
             .values()
+            .filter(|r| is_published(r, now) && r.view_count <= max_views && !followed.contains(&r.id))
+            .collect();
+        neglected.sort_by_key(|r| (r.created_at, r.id));
+        neglected
+            .into_iter()
             .skip(page * PAGE_SIZE)
             .take(PAGE_SIZE)
-            .cloned()
+            .map(|r| masked(r.clone()))
+            .collect()
+    })
+}
+
+#[ic_cdk_macros::query]
+fn get_helpfulness(id: u64) -> EczemaResult<u64> {
+    get_resource(id)?;
+    Ok(helpfulness_scores().get(&id).copied().unwrap_or_default())
+}
+
+#[ic_cdk_macros::query]
+fn list_by_helpfulness(page: usize) -> Vec<EczemaResource> {
+    let caller = ic_cdk::caller();
+    let now = get_timestamp();
+    let scores = helpfulness_scores();
+    let mut ranked: Vec<(u64, EczemaResource)> = STATE.with(|state| {
+        state
+            .borrow()
+            .resources
+            .values()
+            .filter(|r| is_visible_to(r, caller, now))
+            .map(|r| (scores.get(&r.id).copied().unwrap_or_default(), masked(r.clone())))
             .collect()
+    });
+    ranked.sort_by(|(a_score, a), (b_score, b)| b_score.cmp(a_score).then(a.id.cmp(&b.id)));
+    ranked
+        .into_iter()
+        .skip(page * PAGE_SIZE)
+        .take(PAGE_SIZE)
+        .map(|(_, r)| r)
+        .collect()
+}
+
+fn quality_score(resource: &EczemaResource, flag_count: u64, age_days: u64) -> u8 {
+    // Weights add up to 100: verification 35, description 30, source 15,
+    // title 10, freshness 10. Each flag then takes off 10, down to 0.
+    let mut score: u8 = 0;
+    if resource.verified {
+        score += 35;
+    }
+    score += (resource.description.len().min(300) * 30 / 300) as u8;
+    if resource.source_url.is_some() {
+        score += 15;
+    }
+    score += (resource.title.len().min(20) * 10 / 20) as u8;
+    if age_days <= 365 {
+        score += 10;
+    }
+    score.saturating_sub(u8::try_from(flag_count.saturating_mul(10)).unwrap_or(u8::MAX))
+}
+
+fn flag_count(id: u64) -> u64 {
+    FLAGS.with(|flags| flags.borrow().get(&id).map_or(0, |f| f.len() as u64))
+}
+
+fn freshness(resource: &EczemaResource, now: u64) -> Freshness {
+    let age_days = now.saturating_sub(resource.updated_at) / SECONDS_PER_DAY;
+    let thresholds = FRESHNESS_THRESHOLDS.with(|t| *t.borrow());
+    let level = if age_days >= thresholds.stale_after_days {
+        FreshnessLevel::Stale
+    } else if age_days >= thresholds.aging_after_days {
+        FreshnessLevel::Aging
+    } else {
+        FreshnessLevel::Fresh
+    };
+    Freshness { level, age_days }
+}
+
+#[ic_cdk_macros::query]
+fn get_freshness(id: u64) -> EczemaResult<Freshness> {
+    let resource = find_visible(id)?;
+    Ok(freshness(&resource, get_timestamp()))
+}
+
+// Least recently updated first, so maintainers start with the oldest advice.
+#[ic_cdk_macros::query]
+fn list_stale(page: usize) -> ListResponse {
+    let caller = ic_cdk::caller();
+    let now = get_timestamp();
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut stale: Vec<&EczemaResource> = state
+            .resources
+            .values()
+            .filter(|r| is_visible_to(r, caller, now) && freshness(r, now).level == FreshnessLevel::Stale)
+            .collect();
+        stale.sort_by_key(|r| (r.updated_at, r.id));
+        build_page(stale.into_iter(), page)
+    })
+}
+
+#[ic_cdk_macros::query]
+fn get_freshness_thresholds() -> FreshnessThresholds {
+    FRESHNESS_THRESHOLDS.with(|t| *t.borrow())
+}
+
+#[ic_cdk_macros::update(guard = "note_caller")]
+fn set_freshness_thresholds(thresholds: FreshnessThresholds) -> EczemaResult<()> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    if thresholds.aging_after_days == 0 || thresholds.aging_after_days >= thresholds.stale_after_days {
+        return Err(EczemaError::InvalidInput(
+            "Thresholds must satisfy 1 <= aging_after_days < stale_after_days".to_string(),
+        ));
+    }
+    FRESHNESS_THRESHOLDS.with(|t| *t.borrow_mut() = thresholds);
+    Ok(())
+}
+
+#[ic_cdk_macros::query]
+fn get_quality_report(id: u64) -> EczemaResult<QualityReport> {
+    let resource = get_resource(id)?;
+    let age_days = get_timestamp().saturating_sub(resource.created_at) / SECONDS_PER_DAY;
+    let flag_count = flag_count(id);
+
+    Ok(QualityReport {
+        id,
+        title_length: resource.title.len() as u64,
+        description_length: resource.description.len() as u64,
+        verified: resource.verified,
+        has_source: resource.source_url.is_some(),
+        flag_count,
+        age_days,
+        score: quality_score(&resource, flag_count, age_days),
+    })
+}
+
+#[ic_cdk_macros::query]
+fn find_invalid_resources() -> Vec<(u64, String)> {
+    if !is_admin(ic_cdk::caller()) {
+        return Vec::new();
+    }
+
+    STATE.with(|state| {
+        let mut invalid: Vec<(u64, String)> = state
+            .borrow()
+            .resources
+            .values()
+            .filter_map(|r| match validate_payload(&payload_from_resource(r)) {
+                Ok(()) => None,
+                Err(EczemaError::InvalidInput(reason)) => Some((r.id, reason)),
+                Err(EczemaError::ValidationErrors(errors)) => Some((r.id, errors.join("; "))),
+                Err(_) => Some((r.id, "Validation failed".to_string())),
+            })
+            .collect();
+        invalid.sort_by_key(|(id, _)| *id);
+        invalid
     })
 }
 
+// Widely viewed unverified content is the riskiest, so each view weighs as much
+// as ten days of waiting in the queue. A flag is a reader saying something is
+// wrong, and weighs as much as ten views.
+fn review_priority(resource: &EczemaResource, flag_count: u64, now: u64) -> u64 {
+    let age_days = now.saturating_sub(resource.created_at) / SECONDS_PER_DAY;
+    flag_count
+        .saturating_mul(100)
+        .saturating_add(resource.view_count.saturating_mul(10))
+        .saturating_add(age_days)
+}
+
+// Moderator-only: it lists every due, unverified resource that isn't hidden,
+// including ones in categories that keep unverified resources from readers.
 #[ic_cdk_macros::query]
-fn list_resources_by_category(category: ResourceCategory, page: usize) -> Vec<EczemaResource> {
-    CATEGORY_INDEX.with(|category_index| {
-        ECZEMA_RESOURCES.with(|resources| {
-            category_index
+fn review_queue(page: usize) -> Vec<ReviewItem> {
+    if role_of(ic_cdk::caller()) < Role::Moderator {
+        return Vec::new();
+    }
+    let now = get_timestamp();
+    let mut queue: Vec<ReviewItem> = FLAGS.with(|flags| {
+        let flags = flags.borrow();
+        STATE.with(|state| {
+            state
                 .borrow()
-                .get(&category)
-                .map(|ids| {
-                    ids.iter()
-                        .skip(page * PAGE_SIZE)
-                        .take(PAGE_SIZE)
-                        .filter_map(|id| resources.borrow().get(id).cloned())
-                        .collect()
+                .resources
+                .values()
+                .filter(|r| !r.verified && is_due(r, now) && !is_hidden(r.id))
+                .map(|r| {
+                    let flag_count = flags.get(&r.id).map_or(0, |f| f.len() as u64);
+                    ReviewItem {
+                        resource: masked(r.clone()),
+                        priority: review_priority(r, flag_count, now),
+                    }
                 })
-                .unwrap_or_default()
+                .collect()
         })
-    })
+    });
+    queue.sort_by(|a, b| b.priority.cmp(&a.priority).then(a.resource.id.cmp(&b.resource.id)));
+    queue.into_iter().skip(page * PAGE_SIZE).take(PAGE_SIZE).collect()
 }
 
-#[ic_cdk_macros::update]
-fn update_resource(id: u64, payload: CreateResourcePayload) -> EczemaResult<EczemaResource> {
-    validate_payload(&payload)?;
-    let caller = ic_cdk::caller();
+#[ic_cdk_macros::query]
+fn storage_breakdown() -> StorageBreakdown {
+    let (resources, compression_saved, indexes) = STATE.with(|state| {
+        let state = state.borrow();
+        let compression_saved: usize = state
+            .resources
+            .values()
+            .map(|r| r.description.len() - r.description.stored_len())
+            .sum();
+        let resources = state.resources.values().map(approximate_size).sum::<usize>() - compression_saved;
+        let indexes = state.category_index.len() as usize * 16
+            + state.title_index.iter().map(|(title, _)| title.len() + 8).sum::<usize>()
+            + state.external_index.keys().map(|key| key.len() + 8).sum::<usize>()
+            + state.reading_level_index.len() * 16
+            + state.document_frequency.keys().map(|term| term.len() + 8).sum::<usize>();
+        (resources, compression_saved, indexes)
+    });
+    let notifications = NOTIFICATIONS.with(|n| n.borrow().len()) * std::mem::size_of::<Notification>()
+        + SUBSCRIPTIONS.with(|s| s.borrow().values().map(|f| 8 + f.len() * PRINCIPAL_BYTES).sum::<usize>());
+    let changelog = CHANGELOG.with(|c| c.borrow().len()) * std::mem::size_of::<ChangeEvent>()
+        + TOMBSTONES.with(|t| t.borrow().len()) * 16;
+    let flags = FLAGS.with(|f| {
+        f.borrow()
+            .values()
+            .flatten()
+            .map(|flag| {
+                let note = match &flag.reason {
+                    FlagReason::Other(note) => note.len(),
+                    _ => 0,
+                };
+                PRINCIPAL_BYTES + 16 + note
+            })
+            .sum::<usize>()
+    });
+    let viewers = VIEWERS.with(|v| v.borrow().values().map(|set| 8 + set.len() * PRINCIPAL_BYTES).sum::<usize>());
+    let collections = COLLECTIONS.with(|c| {
+        c.borrow()
+            .values()
+            .map(|collection| collection.title.len() + PRINCIPAL_BYTES + 32 + collection.resource_ids.len() * 8)
+            .sum::<usize>()
+    });
+    let links = LINKS.with(|l| l.borrow().values().map(|to| 8 + to.len() * 8).sum::<usize>());
+    let status_history = STATUS_HISTORY.with(|h| {
+        h.borrow()
+            .values()
+            .map(|events| 8 + events.len() * std::mem::size_of::<StatusEvent>())
+            .sum::<usize>()
+    });
+    let moderation_times = MODERATION_TIMES.with(|t| t.borrow().len()) * (8 + std::mem::size_of::<ModerationTimes>());
+    let accounts = FIRST_SEEN.with(|s| s.borrow().len()) * (PRINCIPAL_BYTES + 8)
+        + ACKED_POLICY.with(|a| a.borrow().len()) * (PRINCIPAL_BYTES + 4);
 
-    ECZEMA_RESOURCES.with(|resources| {
-        let mut resources = resources.borrow_mut();
-        if let Some(resource) = resources.get_mut(&id) {
-            if resource.created_by != caller && !is_admin(caller) {
-                return Err(EczemaError::Unauthorized);
+    let parts = [
+        resources,
+        indexes,
+        notifications,
+        changelog,
+        flags,
+        viewers,
+        collections,
+        links,
+        status_history,
+        moderation_times,
+        accounts,
+    ];
+    StorageBreakdown {
+        resources: resources as u64,
+        compression_saved: compression_saved as u64,
+        indexes: indexes as u64,
+        notifications: notifications as u64,
+        changelog: changelog as u64,
+        flags: flags as u64,
+        viewers: viewers as u64,
+        collections: collections as u64,
+        links: links as u64,
+        status_history: status_history as u64,
+        moderation_times: moderation_times as u64,
+        accounts: accounts as u64,
+        total: parts.iter().sum::<usize>() as u64,
+    }
+}
+
+// Raw index contents, for seeing exactly what audit_indexes is complaining
+// about. Categories with no entries are left out rather than filled in.
+#[ic_cdk_macros::query]
+fn dump_category_index() -> EczemaResult<Vec<(ResourceCategory, Vec<u64>)>> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    STATE.with(|state| {
+        let mut dump: Vec<(ResourceCategory, Vec<u64>)> = Vec::new();
+        for ((category, id), _) in state.borrow().category_index.iter() {
+            match dump.last_mut() {
+                Some((last, ids)) if *last == category => ids.push(id),
+                _ => dump.push((category, vec![id])),
             }
-            resource.title = payload.title;
-            resource.description = payload.description;
-            resource.category = payload.category;
-            resource.updated_at = get_timestamp();
-            Ok(resource.clone())
-        } else {
-            Err(EczemaError::NotFound)
         }
+        Ok(dump)
     })
 }
 
-#[ic_cdk_macros::update]
-fn delete_resource(id: u64) -> EczemaResult<()> {
-    let caller = ic_cdk::caller();
-    if !is_admin(caller) {
+#[ic_cdk_macros::query]
+fn dump_title_index() -> EczemaResult<Vec<(String, u64)>> {
+    if !is_admin(ic_cdk::caller()) {
         return Err(EczemaError::Unauthorized);
     }
+    Ok(STATE.with(|state| state.borrow().title_index.iter().cloned().collect()))
+}
 
-    ECZEMA_RESOURCES.with(|resources| {
-        CATEGORY_INDEX.with(|category_index| {
-            if let Some(resource) = resources.borrow_mut().remove(&id) {
-                if let Some(category_ids) = category_index.borrow_mut().get_mut(&resource.category) {
-                    category_ids.retain(|&x| x != id);
-                }
-                Ok(())
-            } else {
-                Err(EczemaError::NotFound)
+#[ic_cdk_macros::query]
+fn audit_indexes() -> IndexAudit {
+    STATE.with(|state| {
+        let state = state.borrow();
+        let resources = &state.resources;
+
+        let category = {
+            let index = &state.category_index;
+            IndexHealth {
+                orphaned_entries: index
+                    .iter()
+                    .filter(|((category, id), _)| resources.get(id).is_none_or(|r| r.category != *category))
+                    .count() as u64,
+                missing_resources: resources
+                    .values()
+                    .filter(|r| !index.contains_key(&(r.category, r.id)))
+                    .count() as u64,
             }
-        })
+        };
+
+        let title = {
+            let index = &state.title_index;
+            IndexHealth {
+                orphaned_entries: index
+                    .iter()
+                    .filter(|(title, id)| resources.get(id).is_none_or(|r| normalize_title(&r.title) != *title))
+                    .count() as u64,
+                missing_resources: resources
+                    .values()
+                    .filter(|r| !index.contains(&(normalize_title(&r.title), r.id)))
+                    .count() as u64,
+            }
+        };
+
+        IndexAudit { category, title }
     })
 }
 
-#[ic_cdk_macros::update]
-fn verify_resource(id: u64) -> EczemaResult<EczemaResource> {
-    let caller = ic_cdk::caller();
-    if !is_admin(caller) {
-        return Err(EczemaError::Unauthorized);
+fn render_metrics() -> String {
+    let (resources_total, verified_total) = STATE.with(|state| {
+        let state = state.borrow();
+        let resources = &state.resources;
+        (resources.len(), resources.values().filter(|r| r.verified).count())
+    });
+    let metrics = [
+        ("eczema_resources_total", "gauge", "Resources currently stored.", resources_total as u128),
+        ("eczema_verified_total", "gauge", "Resources currently verified.", verified_total as u128),
+        ("eczema_resources_created_total", "counter", "Resources created over the canister's lifetime.", get_total_created() as u128),
+        ("eczema_cycles_balance", "gauge", "Cycles held by the canister.", ic_cdk::api::canister_balance128()),
+    ];
+
+    let mut body = String::new();
+    for (name, kind, help, value) in metrics {
+        body.push_str(&format!("# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"));
     }
+    body
+}
 
-    ECZEMA_RESOURCES.with(|resources| {
-        let mut resources = resources.borrow_mut();
-        if let Some(resource) = resources.get_mut(&id) {
-            resource.verified = true;
-            resource.updated_at = get_timestamp();
-            Ok(resource.clone())
-        } else {
-            Err(EczemaError::NotFound)
-        }
-    })
+// Responses are not certified: the cycles balance changes between calls, so
+// there is no stable value to certify. The certifying gateway (icp0.io)
+// rejects them, and scrapers must use the raw domain (<id>.raw.icp0.io).
+#[ic_cdk_macros::query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    let path = request.url.split('?').next().unwrap_or_default();
+    if request.method != "GET" || path != "/metrics" {
+        return HttpResponse {
+            status_code: 404,
+            headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+            body: b"Not found".to_vec(),
+        };
+    }
+
+    HttpResponse {
+        status_code: 200,
+        headers: vec![("Content-Type".to_string(), "text/plain; version=0.0.4".to_string())],
+        body: render_metrics().into_bytes(),
+    }
 }
 
+// Capabilities clients can feature-detect. Add to this list whenever a new
+// optional subsystem ships so older frontends keep working against it.
+const SUPPORTED_FEATURES: &[&str] = &[
+    "scheduled_publishing",
+    "subscriptions",
+    "collections",
+    "templates",
+    "edit_locks",
+    "roles",
+    "principal_links",
+    "source_urls",
+    "see_also_links",
+    "disclaimers",
+    "ranked_search",
+    "metrics",
+    "external_ids",
+    "flags",
+    "content_hashes",
+    "resource_locks",
+    "featured",
+    "homepage_snapshot",
+    "reading_levels",
+    "tombstones",
+    "banned_word_masking",
+    "changelog",
+    "new_account_cooldown",
+    "subcategories",
+    "freshness",
+    "ranked_listing",
+    "policy_acceptance",
+    "shadowbans",
+    "status_history",
+    "verification_requirements",
+    "recommendations",
+    "cacheable_ids",
+];
+
 #[ic_cdk_macros::query]
-fn search_resources(query: String, page: usize) -> Vec<EczemaResource> {
-    let query = query.to_lowercase();
-    ECZEMA_RESOURCES.with(|resources| {
-        resources
-            .borrow()
-            .values()
-            .filter(|r| {
-                r.title.to_lowercase().contains(&query) ||
-                r.description.to_lowercase().contains(&query)
-            })
-            .skip(page * PAGE_SIZE)
-            .take(PAGE_SIZE)
-            .cloned()
-            .collect()
-    })
+fn api_version() -> ApiInfo {
+    ApiInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        features: SUPPORTED_FEATURES.iter().map(|f| f.to_string()).collect(),
+        // Set GIT_COMMIT in the build environment to embed it.
+        git_commit: option_env!("GIT_COMMIT").map(str::to_string),
+    }
 }
 
 #[ic_cdk_macros::init]
 fn init() {
     let caller = ic_cdk::caller();
     ADMINS.with(|admins| admins.borrow_mut().push(caller));
+    start_homepage_refresh();
+    start_publish_sweep();
 }
 
 #[ic_cdk_macros::pre_upgrade]
 fn pre_upgrade() {
-    let resources = ECZEMA_RESOURCES.with(|r| r.borrow().clone());
-    let category_index = CATEGORY_INDEX.with(|c| c.borrow().clone());
-    let next_id = NEXT_ID.with(|n| *n.borrow());
-    let admins = ADMINS.with(|a| a.borrow().clone());
-    storage::stable_save((resources, category_index, next_id, admins)).unwrap();
+    let bytes = encode_upgrade_state();
+    let mut memory = MEMORY_MANAGER.with(|m| m.borrow().get(UPGRADE_STATE_MEMORY_ID));
+    let mut writer = Writer::new(&mut memory, 0);
+    writer.write(&(bytes.len() as u64).to_le_bytes()).unwrap();
+    writer.write(&bytes).unwrap();
+}
+
+// Bytes pre_upgrade would write right now, length header included. The
+// category index already lives in stable memory and isn't part of it.
+#[ic_cdk_macros::query]
+fn upgrade_size_estimate() -> EczemaResult<u64> {
+    if !is_admin(ic_cdk::caller()) {
+        return Err(EczemaError::Unauthorized);
+    }
+    Ok(encode_upgrade_state().len() as u64 + 8)
+}
+
+fn encode_upgrade_state() -> Vec<u8> {
+    let state = StableState {
+        resources: STATE.with(|s| s.borrow().resources.clone()),
+        next_id: STATE.with(|s| s.borrow().next_id),
+        admins: ADMINS.with(|a| a.borrow().clone()),
+        default_sort: DEFAULT_SORT.with(|s| *s.borrow()),
+        subscriptions: SUBSCRIPTIONS.with(|s| s.borrow().clone()),
+        notifications: NOTIFICATIONS.with(|n| n.borrow().clone()),
+        next_notification_seq: NEXT_NOTIFICATION_SEQ.with(|n| *n.borrow()),
+        notification_cursors: NOTIFICATION_CURSORS.with(|c| c.borrow().clone()),
+        banned_words: BANNED_WORDS.with(|b| b.borrow().clone()),
+        total_created: STATE.with(|s| s.borrow().total_created),
+        announcement: ANNOUNCEMENT.with(|a| a.borrow().clone()),
+        roles: ROLES.with(|r| r.borrow().clone()),
+        restricted_categories: RESTRICTED_CATEGORIES.with(|r| r.borrow().clone()),
+        collections: COLLECTIONS.with(|c| c.borrow().clone()),
+        next_collection_id: NEXT_COLLECTION_ID.with(|n| *n.borrow()),
+        templates: TEMPLATES.with(|t| t.borrow().clone()),
+        next_template_id: NEXT_TEMPLATE_ID.with(|n| *n.borrow()),
+        principal_links: PRINCIPAL_LINKS.with(|l| l.borrow().clone()),
+        pending_links: PENDING_LINKS.with(|l| l.borrow().clone()),
+        auto_unverify_on_edit: AUTO_UNVERIFY_ON_EDIT.with(|f| *f.borrow()),
+        category_limits: CATEGORY_LIMITS.with(|l| l.borrow().clone()),
+        disclaimer_text: DISCLAIMER_TEXT.with(|t| t.borrow().clone()),
+        links: LINKS.with(|l| l.borrow().clone()),
+        viewers: VIEWERS.with(|v| v.borrow().clone()),
+        spam_threshold: SPAM_THRESHOLD.with(|t| *t.borrow()),
+        flags: Some(FLAGS.with(|f| f.borrow().clone())),
+        locked_resources: Some(LOCKED_RESOURCES.with(|l| l.borrow().clone())),
+        featured: Some(FEATURED.with(|f| f.borrow().clone())),
+        featured_impressions: Some(FEATURED_IMPRESSIONS.with(|i| i.borrow().clone())),
+        tombstones: Some(TOMBSTONES.with(|t| t.borrow().clone())),
+        mask_banned: Some(MASK_BANNED.with(|m| *m.borrow())),
+        changelog: Some(CHANGELOG.with(|c| c.borrow().clone())),
+        next_change_seq: Some(NEXT_CHANGE_SEQ.with(|n| *n.borrow())),
+        first_seen: Some(FIRST_SEEN.with(|f| f.borrow().clone())),
+        new_account_cooldown: Some(NEW_ACCOUNT_COOLDOWN.with(|c| *c.borrow())),
+        subcategories: Some(SUBCATEGORIES.with(|s| s.borrow().clone())),
+        freshness_thresholds: Some(FRESHNESS_THRESHOLDS.with(|t| *t.borrow())),
+        ranking_weights: Some(RANKING_WEIGHTS.with(|w| *w.borrow())),
+        notify_target: NOTIFY_TARGET.with(|t| *t.borrow()),
+        policy_version: Some(POLICY_VERSION.with(|v| *v.borrow())),
+        acked_policy: Some(ACKED_POLICY.with(|a| a.borrow().clone())),
+        shadowbanned: Some(SHADOWBANNED.with(|s| s.borrow().clone())),
+        hidden_resources: Some(HIDDEN_RESOURCES.with(|h| h.borrow().clone())),
+        status_history: Some(STATUS_HISTORY.with(|h| h.borrow().clone())),
+        moderation_times: Some(MODERATION_TIMES.with(|t| t.borrow().clone())),
+        requires_verification: Some(REQUIRES_VERIFICATION.with(|r| r.borrow().clone())),
+        trusted_authors: Some(TRUSTED_AUTHORS.with(|t| t.borrow().clone())),
+        last_publish_sweep: Some(LAST_PUBLISH_SWEEP.with(|l| *l.borrow())),
+    };
+    candid::encode_one(VersionedState::V2(state)).unwrap()
+}
+
+// The first release wrote its state with storage::stable_save, which starts
+// with the candid magic bytes rather than the memory manager header. This must
+// be checked before MEMORY_MANAGER is first touched, since initializing it
+// overwrites that data.
+fn has_legacy_stable_layout() -> bool {
+    if ic_cdk::api::stable::stable_size() == 0 {
+        return false;
+    }
+    let mut magic = [0u8; 4];
+    ic_cdk::api::stable::stable_read(0, &mut magic);
+    &magic == b"DIDL"
+}
+
+fn read_upgrade_state() -> VersionedState {
+    let memory = MEMORY_MANAGER.with(|m| m.borrow().get(UPGRADE_STATE_MEMORY_ID));
+    let mut len_bytes = [0u8; 8];
+    memory.read(0, &mut len_bytes);
+    let mut bytes = vec![0u8; u64::from_le_bytes(len_bytes) as usize];
+    memory.read(8, &mut bytes);
+    candid::decode_one(&bytes).unwrap()
+}
+
+fn migrate_state(state: VersionedState) -> StableState {
+    let (resources, legacy_index, next_id, admins) = match state {
+        VersionedState::V2(state) => return state,
+        VersionedState::V1(state) => state,
+    };
+
+    STATE.with(|s| {
+        let index = &mut s.borrow_mut().category_index;
+        for (category, ids) in legacy_index {
+            for id in ids {
+                index.insert((category, id), ());
+            }
+        }
+    });
+    let resources: HashMap<u64, EczemaResource> =
+        resources.into_iter().map(|(id, r)| (id, EczemaResource::from(r))).collect();
+    StableState {
+        total_created: resources.len() as u64,
+        resources,
+        next_id,
+        admins,
+        default_sort: SortOrder::NewestFirst,
+        subscriptions: HashMap::new(),
+        notifications: VecDeque::new(),
+        next_notification_seq: 1,
+        notification_cursors: HashMap::new(),
+        banned_words: Vec::new(),
+        announcement: None,
+        roles: HashMap::new(),
+        restricted_categories: HashMap::new(),
+        collections: HashMap::new(),
+        next_collection_id: 1,
+        templates: HashMap::new(),
+        next_template_id: 1,
+        principal_links: HashMap::new(),
+        pending_links: HashMap::new(),
+        auto_unverify_on_edit: false,
+        category_limits: HashMap::new(),
+        disclaimer_text: DEFAULT_DISCLAIMER.to_string(),
+        links: HashMap::new(),
+        viewers: HashMap::new(),
+        spam_threshold: None,
+        flags: None,
+        locked_resources: None,
+        featured: None,
+        featured_impressions: None,
+        tombstones: None,
+        mask_banned: None,
+        changelog: None,
+        next_change_seq: None,
+        first_seen: None,
+        new_account_cooldown: None,
+        subcategories: None,
+        freshness_thresholds: None,
+        ranking_weights: None,
+        notify_target: None,
+        policy_version: None,
+        acked_policy: None,
+        shadowbanned: None,
+        hidden_resources: None,
+        status_history: None,
+        moderation_times: None,
+        requires_verification: None,
+        trusted_authors: None,
+        last_publish_sweep: None,
+    }
 }
 
 #[ic_cdk_macros::post_upgrade]
 fn post_upgrade() {
-    let (resources, category_index, next_id, admins): (
-        HashMap<u64, EczemaResource>,
-        BTreeMap<ResourceCategory, Vec<u64>>,
-        u64,
-        Vec<Principal>,
-    ) = storage::stable_restore().unwrap();
-    ECZEMA_RESOURCES.with(|r| *r.borrow_mut() = resources);
-    CATEGORY_INDEX.with(|c| *c.borrow_mut() = category_index);
-    NEXT_ID.with(|n| *n.borrow_mut() = next_id);
-    ADMINS.with(|a| *a.borrow_mut() = admins);
+    let state = migrate_state(if has_legacy_stable_layout() {
+        VersionedState::V1(storage::stable_restore().unwrap())
+    } else {
+        read_upgrade_state()
+    });
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        s.resources = state.resources;
+        s.next_id = state.next_id;
+        s.total_created = state.total_created;
+        s.rebuild_derived_indexes();
+    });
+    ADMINS.with(|a| *a.borrow_mut() = state.admins);
+    DEFAULT_SORT.with(|s| *s.borrow_mut() = state.default_sort);
+    SUBSCRIPTIONS.with(|s| *s.borrow_mut() = state.subscriptions);
+    NOTIFICATIONS.with(|n| *n.borrow_mut() = state.notifications);
+    NEXT_NOTIFICATION_SEQ.with(|n| *n.borrow_mut() = state.next_notification_seq);
+    NOTIFICATION_CURSORS.with(|c| *c.borrow_mut() = state.notification_cursors);
+    BANNED_WORDS.with(|b| *b.borrow_mut() = state.banned_words);
+    ANNOUNCEMENT.with(|a| *a.borrow_mut() = state.announcement);
+    ROLES.with(|r| *r.borrow_mut() = state.roles);
+    RESTRICTED_CATEGORIES.with(|r| *r.borrow_mut() = state.restricted_categories);
+    COLLECTIONS.with(|c| *c.borrow_mut() = state.collections);
+    NEXT_COLLECTION_ID.with(|n| *n.borrow_mut() = state.next_collection_id);
+    TEMPLATES.with(|t| *t.borrow_mut() = state.templates);
+    NEXT_TEMPLATE_ID.with(|n| *n.borrow_mut() = state.next_template_id);
+    PRINCIPAL_LINKS.with(|l| *l.borrow_mut() = state.principal_links);
+    PENDING_LINKS.with(|l| *l.borrow_mut() = state.pending_links);
+    AUTO_UNVERIFY_ON_EDIT.with(|f| *f.borrow_mut() = state.auto_unverify_on_edit);
+    CATEGORY_LIMITS.with(|l| *l.borrow_mut() = state.category_limits);
+    DISCLAIMER_TEXT.with(|t| *t.borrow_mut() = state.disclaimer_text);
+    LINKS.with(|l| *l.borrow_mut() = state.links);
+    VIEWERS.with(|v| *v.borrow_mut() = state.viewers);
+    SPAM_THRESHOLD.with(|t| *t.borrow_mut() = state.spam_threshold);
+    FLAGS.with(|f| *f.borrow_mut() = state.flags.unwrap_or_default());
+    LOCKED_RESOURCES.with(|l| *l.borrow_mut() = state.locked_resources.unwrap_or_default());
+    FEATURED.with(|f| *f.borrow_mut() = state.featured.unwrap_or_default());
+    FEATURED_IMPRESSIONS.with(|i| *i.borrow_mut() = state.featured_impressions.unwrap_or_default());
+    TOMBSTONES.with(|t| *t.borrow_mut() = state.tombstones.unwrap_or_default());
+    MASK_BANNED.with(|m| *m.borrow_mut() = state.mask_banned.unwrap_or_default());
+    CHANGELOG.with(|c| *c.borrow_mut() = state.changelog.unwrap_or_default());
+    NEXT_CHANGE_SEQ.with(|n| *n.borrow_mut() = state.next_change_seq.unwrap_or(1));
+    FIRST_SEEN.with(|f| *f.borrow_mut() = state.first_seen.unwrap_or_default());
+    NEW_ACCOUNT_COOLDOWN.with(|c| *c.borrow_mut() = state.new_account_cooldown.unwrap_or_default());
+    SUBCATEGORIES.with(|s| *s.borrow_mut() = state.subcategories.unwrap_or_default());
+    FRESHNESS_THRESHOLDS
+        .with(|t| *t.borrow_mut() = state.freshness_thresholds.unwrap_or(DEFAULT_FRESHNESS_THRESHOLDS));
+    RANKING_WEIGHTS.with(|w| *w.borrow_mut() = state.ranking_weights.unwrap_or(DEFAULT_RANKING_WEIGHTS));
+    NOTIFY_TARGET.with(|t| *t.borrow_mut() = state.notify_target);
+    POLICY_VERSION.with(|v| *v.borrow_mut() = state.policy_version.unwrap_or_default());
+    ACKED_POLICY.with(|a| *a.borrow_mut() = state.acked_policy.unwrap_or_default());
+    SHADOWBANNED.with(|s| *s.borrow_mut() = state.shadowbanned.unwrap_or_default());
+    HIDDEN_RESOURCES.with(|h| *h.borrow_mut() = state.hidden_resources.unwrap_or_default());
+    STATUS_HISTORY.with(|h| *h.borrow_mut() = state.status_history.unwrap_or_default());
+    MODERATION_TIMES.with(|t| *t.borrow_mut() = state.moderation_times.unwrap_or_default());
+    REQUIRES_VERIFICATION.with(|r| *r.borrow_mut() = state.requires_verification.unwrap_or_default());
+    TRUSTED_AUTHORS.with(|t| *t.borrow_mut() = state.trusted_authors.unwrap_or_default());
+    // Without a saved sweep, schedules that passed before this upgrade are not announced.
+    LAST_PUBLISH_SWEEP.with(|l| *l.borrow_mut() = state.last_publish_sweep.unwrap_or_else(get_timestamp));
+    start_homepage_refresh();
+    start_publish_sweep();
 }
 
 // Export the Candid interface
-ic_cdk::export_candid!();
\ No newline at end of file
+ic_cdk::export_candid!();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn payload(title: &str) -> CreateResourcePayload {
+        CreateResourcePayload {
+            title: title.to_string(),
+            description: format!("{title} description long enough to pass validation."),
+            category: ResourceCategory::Prevention,
+            publish_at: None,
+            source_url: None,
+            reading_level: Some(ReadingLevel::Basic),
+            subcategory: None,
+        }
+    }
+
+    #[test]
+    fn purge_leaves_no_reference_to_the_id() {
+        let owner = Principal::anonymous();
+        let (kept, purged) = STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let kept = state.insert(payload("Kept"), owner, NOW).id;
+            let purged = state.insert(payload("Purgeable"), owner, NOW).id;
+            state.set_external_id(purged, "cms-1".to_string());
+            (kept, purged)
+        });
+        LINKS.with(|links| {
+            let mut links = links.borrow_mut();
+            links.insert(kept, vec![purged]);
+            links.insert(purged, vec![kept]);
+        });
+        SUBSCRIPTIONS.with(|s| s.borrow_mut().insert(purged, vec![owner]));
+        FEATURED.with(|f| f.borrow_mut().insert(purged));
+        HIDDEN_RESOURCES.with(|h| h.borrow_mut().insert(purged));
+
+        assert!(purge_resource(purged, NOW).is_some());
+
+        STATE.with(|state| {
+            let state = state.borrow();
+            assert!(!state.resources.contains_key(&purged));
+            assert!(state.category_index.iter().all(|((_, id), _)| id != purged));
+            assert!(state.title_index.iter().all(|&(_, id)| id != purged));
+            assert!(state.reading_level_index.iter().all(|&(_, id)| id != purged));
+            assert!(!state.external_index.contains_key("cms-1"));
+            assert!(!state.document_frequency.contains_key("purgeable"));
+            assert!(state.resources.contains_key(&kept));
+        });
+        assert!(LINKS.with(|links| links.borrow().is_empty()));
+        assert!(!SUBSCRIPTIONS.with(|s| s.borrow().contains_key(&purged)));
+        assert!(!FEATURED.with(|f| f.borrow().contains(&purged)));
+        assert!(!is_hidden(purged));
+        assert!(TOMBSTONES.with(|t| t.borrow().iter().any(|&(id, _)| id == purged)));
+    }
+
+    #[test]
+    fn rejected_payloads_leave_no_id_gaps() {
+        let owner = Principal::anonymous();
+        let mut empty_title = payload("Rejected");
+        empty_title.title.clear();
+        let ids: Vec<u64> = [payload("First"), empty_title, payload("Second")]
+            .into_iter()
+            .filter_map(|p| validate_payload(&p).ok().map(|()| p))
+            .map(|p| STATE.with(|state| state.borrow_mut().insert(p, owner, NOW).id))
+            .collect();
+        assert_eq!(ids.len(), 2);
+        assert_eq!(ids[1], ids[0] + 1);
+        assert_eq!(STATE.with(|state| state.borrow().next_id), ids[1] + 1);
+    }
+}